@@ -0,0 +1,202 @@
+#!/usr/bin/env python3
+#
+# Copyright (c) 2019 Jonathan Perkin <jonathan@perkin.org.uk>
+#
+# Permission to use, copy, modify, and distribute this software for any
+# purpose with or without fee is hereby granted, provided that the above
+# copyright notice and this permission notice appear in all copies.
+#
+# THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+# WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+# MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+# ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+# WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+# ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+# OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+#
+# bench.py - time pm against a generated repository.
+#
+# A pkg_summary of 20,000 packages (by default), each with three
+# dependencies, three requires, one provides and one conflicts entry, is
+# served over HTTP from a temporary directory, along with an empty prefix
+# with stub pkg_install tools and a local pkg-vulnerabilities so that nothing
+# is fetched from the network.  With -m, that many repositories are served,
+# each with the same packages at a different version.  Each command is run
+# against a fresh database, and the best time of each is reported, or "-" if
+# it failed.
+#
+# Usage: benches/bench.py [-n packages] [-m repositories] [-r runs] [path/to/pm]
+#
+# Build with "cargo build --release" first, the default is target/release/pm.
+#
+
+import argparse
+import gzip
+import hashlib
+import http.server
+import os
+import shutil
+import subprocess
+import sys
+import tempfile
+import threading
+import time
+
+COMMANDS = [
+    ["update"],
+    ["update", "--force"],
+    ["avail"],
+    ["search", "pkg1999"],
+    ["search", "--text", "useful thing"],
+    ["info", "pkg12345"],
+    ["repo", "remove", "bench1"],
+]
+
+
+def entry(i, count, repo):
+    version = "%d.%d" % (repo + i % 7, i % 13)
+    lines = [
+        "BUILD_DATE=2019-08-01 12:00:00 +0000",
+        "CATEGORIES=cat%d misc" % (i % 40),
+        "COMMENT=Package number %d" % i,
+        "DESCRIPTION=Description of pkg%d." % i,
+        "DESCRIPTION=It does useful thing number %d." % i,
+        "DESCRIPTION=Third line.",
+        "FILE_NAME=pkg%d-%s.tgz" % (i, version),
+        "FILE_SIZE=1234",
+        "HOMEPAGE=https://example.org/pkg%d" % i,
+        "LICENSE=mit",
+        "MACHINE_ARCH=x86_64",
+        "OPSYS=Linux",
+        "OS_VERSION=5.0",
+        "PKGNAME=pkg%d-%s" % (i, version),
+        "PKGPATH=cat%d/pkg%d" % (i % 40, i),
+        "PKGTOOLS_VERSION=20190405",
+        "SIZE_PKG=5678",
+    ]
+    for d in (1, 2, 3):
+        dep = (i * 7 + d) % count
+        lines.append("DEPENDS=pkg%d>=1.0" % dep)
+        lines.append("REQUIRES=/opt/local/lib/libpkg%d.so.1" % dep)
+    lines.append("PROVIDES=/opt/local/lib/libpkg%d.so.1" % i)
+    lines.append("CONFLICTS=oldpkg%d-[0-9]*" % i)
+    return "\n".join(lines) + "\n"
+
+
+def write_summary(path, count, repo):
+    with gzip.open(path, "wt") as f:
+        f.write("\n".join(entry(i, count, repo) for i in range(count)))
+
+
+def write_vulnerabilities(path, count):
+    lines = ["#FORMAT 1.0.0"]
+    for i in range(0, count, 100):
+        lines.append(
+            "pkg%d<1.5\tdenial-of-service\thttps://example.org/%d" % (i, i)
+        )
+    body = "\n".join(lines) + "\n"
+    checksum = hashlib.sha512(body.encode()).hexdigest()
+    with open(path, "w") as f:
+        f.write("#CHECKSUM SHA512 %s\n%s" % (checksum, body))
+
+
+def write_stub(path, script):
+    with open(path, "w") as f:
+        f.write("#!/bin/sh\n" + script)
+    os.chmod(path, 0o755)
+
+
+class QuietHandler(http.server.SimpleHTTPRequestHandler):
+    def log_message(self, *args):
+        pass
+
+
+def serve(directory):
+    handler = lambda *args: QuietHandler(*args, directory=directory)
+    server = http.server.ThreadingHTTPServer(("127.0.0.1", 0), handler)
+    threading.Thread(target=server.serve_forever, daemon=True).start()
+    return server
+
+
+def main():
+    parser = argparse.ArgumentParser(description="Benchmark pm.")
+    parser.add_argument("-n", type=int, default=20000, help="packages")
+    parser.add_argument("-m", type=int, default=1, help="repositories")
+    parser.add_argument("-r", type=int, default=3, help="runs")
+    parser.add_argument("pm", nargs="?", default="target/release/pm")
+    args = parser.parse_args()
+    pm = os.path.abspath(args.pm)
+    if not os.access(pm, os.X_OK):
+        sys.exit("ERROR: %s is not executable" % pm)
+
+    work = tempfile.mkdtemp(prefix="pm-bench.")
+    try:
+        prefix = os.path.join(work, "prefix")
+        os.makedirs(os.path.join(prefix, "pkg"))
+        os.makedirs(os.path.join(prefix, "sbin"))
+        write_stub(
+            os.path.join(prefix, "sbin", "pkg_admin"),
+            'case "$2" in PKG_DBDIR) echo %s/pkg;; esac\n' % prefix,
+        )
+        write_stub(os.path.join(prefix, "sbin", "pkg_info"), "exit 0\n")
+
+        repo = os.path.join(work, "repo")
+        for r in range(1, args.m + 1):
+            os.makedirs(os.path.join(repo, str(r)))
+            summary = os.path.join(repo, str(r), "pkg_summary.gz")
+            write_summary(summary, args.n, r)
+        server = serve(repo)
+        vulns = os.path.join(work, "pkg-vulnerabilities")
+        write_vulnerabilities(vulns, args.n)
+        config = (
+            'vulnerabilities = "%s"\n'
+            "[[prefix]]\n"
+            '  path = "%s"\n'
+        ) % (vulns, prefix)
+        for r in range(1, args.m + 1):
+            config += (
+                "  [[prefix.repository]]\n"
+                '    name = "bench%d"\n'
+                '    url = "http://127.0.0.1:%d/%d"\n'
+            ) % (r, server.server_address[1], r)
+
+        best = {}
+        for run in range(args.r):
+            data = os.path.join(work, "data%d" % run)
+            os.makedirs(data)
+            cfg = os.path.join(data, "pm.toml")
+            with open(cfg, "w") as f:
+                f.write(config)
+            env = dict(os.environ, XDG_DATA_HOME=data)
+            for cmd in COMMANDS:
+                start = time.monotonic()
+                result = subprocess.run(
+                    [pm, "-c", cfg] + cmd,
+                    env=env,
+                    stdout=subprocess.DEVNULL,
+                    stderr=subprocess.DEVNULL,
+                )
+                elapsed = time.monotonic() - start
+                key = " ".join(cmd)
+                if result.returncode != 0:
+                    best[key] = None
+                elif best.get(key, 0) is not None:
+                    best[key] = min(best.get(key, elapsed), elapsed)
+        server.shutdown()
+
+        print(
+            "%d packages in %d repositor%s, best of %d runs"
+            % (args.n, args.m, "y" if args.m == 1 else "ies", args.r)
+        )
+        for cmd in COMMANDS:
+            key = " ".join(cmd)
+            if best[key] is None:
+                print("%-32s -" % ("pm " + key))
+            else:
+                print("%-32s %.2fs" % ("pm " + key, best[key]))
+    finally:
+        shutil.rmtree(work)
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,520 @@
+/*
+ * Copyright (c) 2019 Jonathan Perkin <jonathan@perkin.org.uk>
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ *
+ * audit.rs - load pkg-vulnerabilities, handle "pm audit" command.
+ */
+
+extern crate openssl;
+
+use crate::config;
+use crate::dewey;
+use crate::fetch;
+use crate::list::PackageList;
+use crate::pmdb::PMDB;
+use crate::select;
+use crate::summary;
+use crate::verify;
+use openssl::hash::{hash, MessageDigest};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::time::SystemTime;
+
+const PGP_MESSAGE: &str = "-----BEGIN PGP SIGNED MESSAGE-----";
+const PGP_SIGNATURE: &str = "-----BEGIN PGP SIGNATURE-----";
+
+/*
+ * A single vulnerability: a package pattern, the type of vulnerability, and
+ * the advisory URL.
+ */
+pub type Vulnerability = (String, String, String);
+
+/*
+ * Vulnerabilities indexed for quick lookups by package name.  Each pattern
+ * (after expanding any alternates) is keyed by the literal text it starts
+ * with, so only the few patterns that could possibly match a package name
+ * need to be tested against it.
+ */
+pub struct Vulnerabilities {
+    entries: Vec<Vulnerability>,
+    index: HashMap<String, Vec<(String, usize)>>,
+}
+
+fn expand_all(pattern: &str, out: &mut Vec<String>) {
+    match dewey::expand_alternates(pattern) {
+        Some(patterns) => {
+            for p in patterns {
+                expand_all(&p, out);
+            }
+        }
+        None => out.push(pattern.to_string()),
+    }
+}
+
+impl Vulnerabilities {
+    pub fn new(entries: Vec<Vulnerability>) -> Vulnerabilities {
+        let mut index: HashMap<String, Vec<(String, usize)>> = HashMap::new();
+        for (i, (pattern, _, _)) in entries.iter().enumerate() {
+            let mut patterns = vec![];
+            expand_all(pattern, &mut patterns);
+            for p in patterns {
+                let end = p.find(['<', '>', '*', '?', '[']).unwrap_or(p.len());
+                index.entry(p[..end].to_string()).or_default().push((p, i));
+            }
+        }
+        Vulnerabilities { entries, index }
+    }
+
+    /*
+     * Load the vulnerabilities recorded in the database.
+     */
+    pub fn load(db: &PMDB) -> rusqlite::Result<Vulnerabilities> {
+        Ok(Vulnerabilities::new(db.get_vulnerabilities()?))
+    }
+
+    /*
+     * Return every vulnerability affecting a package.
+     */
+    pub fn matching(&self, pkgname: &str) -> Vec<&Vulnerability> {
+        let mut found = vec![];
+        let prefixes = pkgname
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(std::iter::once(pkgname.len()));
+        for end in prefixes {
+            for (pattern, i) in
+                self.index.get(&pkgname[..end]).iter().copied().flatten()
+            {
+                if !found.contains(i) && dewey::pkg_match(pattern, pkgname) {
+                    found.push(*i);
+                }
+            }
+        }
+        found.sort_unstable();
+        found.iter().map(|i| &self.entries[*i]).collect()
+    }
+
+    pub fn is_vulnerable(&self, pkgname: &str) -> bool {
+        !self.matching(pkgname).is_empty()
+    }
+}
+
+/*
+ * Check the #CHECKSUM line embedded in the file, which covers everything
+ * apart from the line itself.  Returns the checksum.
+ */
+fn verify_checksum(body: &str) -> Result<String, String> {
+    let start = if body.starts_with("#CHECKSUM ") {
+        0
+    } else {
+        body.find("\n#CHECKSUM ")
+            .map(|i| i + 1)
+            .ok_or("missing #CHECKSUM")?
+    };
+    let end = body[start..]
+        .find('\n')
+        .map(|i| start + i + 1)
+        .unwrap_or(body.len());
+    let mut fields = body[start..end].split_whitespace().skip(1);
+    let (alg, expected) = match (fields.next(), fields.next()) {
+        (Some(alg), Some(expected)) => (alg, expected.to_lowercase()),
+        _ => return Err("invalid #CHECKSUM".to_string()),
+    };
+    let md = MessageDigest::from_name(alg)
+        .ok_or_else(|| format!("unsupported checksum algorithm {}", alg))?;
+    let mut data = body.as_bytes()[..start].to_vec();
+    data.extend_from_slice(&body.as_bytes()[end..]);
+    let computed: String = hash(md, &data)
+        .map_err(|e| e.to_string())?
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    if computed != expected {
+        return Err("checksum mismatch".to_string());
+    }
+    Ok(format!("{}:{}", alg, expected))
+}
+
+/*
+ * Return the text of a clearsigned pkg-vulnerabilities without checking the
+ * signature.
+ */
+fn strip_signature(contents: &str) -> Result<String, String> {
+    let start = contents
+        .find("\n\n")
+        .map(|i| i + 2)
+        .ok_or("invalid PGP header")?;
+    let end = contents
+        .find(&format!("\n{}", PGP_SIGNATURE))
+        .map(|i| i + 1)
+        .ok_or("missing PGP signature")?;
+    Ok(contents[start..end]
+        .lines()
+        .map(|l| l.strip_prefix("- ").unwrap_or(l))
+        .map(|l| format!("{}\n", l))
+        .collect())
+}
+
+/*
+ * Parse the contents of pkg-vulnerabilities, verifying any signature and
+ * always verifying the embedded checksum.  If a keyring is configured the
+ * file must be signed by one of its keys, otherwise the default GPG keyring
+ * is used and a signature that cannot be checked is only warned about.
+ * Returns the checksum and the vulnerabilities.
+ */
+fn parse(
+    cfg: &config::Config,
+    contents: &str,
+) -> Result<(String, Vec<Vulnerability>), String> {
+    let body = if contents.starts_with(PGP_MESSAGE) {
+        let gpg = cfg
+            .get_prefix(cfg.prefix())
+            .map(|p| p.gpg())
+            .unwrap_or_else(|| "gpg".to_string());
+        let keyring = cfg.vulnerabilities_keyring().as_deref();
+        match verify::verify_clearsigned(&gpg, keyring, contents.as_bytes()) {
+            Ok(text) => String::from_utf8(text).map_err(|e| e.to_string())?,
+            Err(verify::GpgError::Unchecked(e)) if keyring.is_none() => {
+                eprintln!(
+                    "WARNING: Unable to check the signature of \
+                     pkg-vulnerabilities: {}",
+                    e
+                );
+                strip_signature(contents)?
+            }
+            Err(e) => return Err(e.to_string()),
+        }
+    } else if cfg.vulnerabilities_keyring().is_some() {
+        return Err("not signed".to_string());
+    } else {
+        contents.to_string()
+    };
+
+    let checksum = verify_checksum(&body)?;
+
+    if !body.lines().any(|l| l.starts_with("#FORMAT 1.")) {
+        return Err("unsupported or missing #FORMAT".to_string());
+    }
+    let mut vulns = vec![];
+    for (n, line) in body.lines().enumerate() {
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 3 {
+            return Err(format!("line {}: invalid entry", n + 1));
+        }
+        vulns.push((
+            fields[0].to_string(),
+            fields[1].to_string(),
+            fields[2].to_string(),
+        ));
+    }
+    Ok((checksum, vulns))
+}
+
+/*
+ * Fetch pkg-vulnerabilities if it has changed since it was last recorded,
+ * and record it in the database.
+ */
+pub fn refresh(
+    cfg: &config::Config,
+    db: &mut PMDB,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let source = cfg.vulnerabilities();
+    let location = source.url();
+    let current = db
+        .get_vulnerability_source()?
+        .filter(|(l, _, _)| l == location);
+
+    let (mtime, data) = if location.contains("://") {
+        let client = fetch::client(cfg, source)?;
+        let res = match &current {
+            Some((_, mtime, _)) => {
+                fetch::get_if_modified(&client, source, location, *mtime)?
+            }
+            None => fetch::get(&client, source, location)?,
+        };
+        if res.status() == reqwest::StatusCode::NOT_MODIFIED {
+            return Ok(());
+        }
+        if !res.status().is_success() {
+            return Err(
+                format!("{} returned {}", location, res.status()).into()
+            );
+        }
+        (res.last_modified().unwrap_or(0), res.body().to_vec())
+    } else {
+        let mtime = fs::metadata(location)
+            .and_then(|m| m.modified())
+            .map_err(|e| format!("{}: {}", location, e))?
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_secs() as i64;
+        if let Some((_, m, _)) = &current {
+            if *m == mtime {
+                return Ok(());
+            }
+        }
+        let data =
+            fs::read(location).map_err(|e| format!("{}: {}", location, e))?;
+        (mtime, data)
+    };
+
+    let mut contents = String::new();
+    match Path::new(location).extension().and_then(|e| e.to_str()) {
+        Some(e @ ("xz" | "bz2" | "gz")) => {
+            summary::decompress(e, &data)?
+                .read_to_string(&mut contents)
+                .map_err(|e| format!("{}: {}", location, e))?;
+        }
+        _ => {
+            contents = String::from_utf8(data)
+                .map_err(|e| format!("{}: {}", location, e))?
+        }
+    }
+    let (checksum, vulns) =
+        parse(cfg, &contents).map_err(|e| format!("{}: {}", location, e))?;
+    db.replace_vulnerabilities(location, mtime, &checksum, &vulns)?;
+    Ok(())
+}
+
+/*
+ * Exit status used when vulnerable packages are installed, and for any error,
+ * such as the vulnerabilities not being loaded at all.
+ */
+const EXIT_VULNERABLE: i32 = 1;
+pub const EXIT_FAILED: i32 = 2;
+
+/*
+ * Find the newest version of an installed package available from any
+ * repository that is not vulnerable, if there is one newer than what is
+ * installed.
+ */
+fn find_fix<'a>(
+    vulns: &Vulnerabilities,
+    pkgname: &str,
+    available: &'a [PackageList],
+) -> Option<&'a PackageList> {
+    let (pkgbase, pkgversion) = pkgname.rsplit_once('-')?;
+    available
+        .iter()
+        .filter(|p| p.pkgbase() == pkgbase)
+        .filter(|p| {
+            dewey::compare(p.pkgversion(), pkgversion) == Ordering::Greater
+        })
+        .filter(|p| !vulns.is_vulnerable(p.pkgname()))
+        .max_by(|a, b| dewey::compare(a.pkgversion(), b.pkgversion()))
+}
+
+/*
+ * Report installed packages with known vulnerabilities in every prefix, and
+ * whether a fixed version is available from a repository.
+ */
+pub fn run(
+    cfg: &config::Config,
+    db: &mut PMDB,
+) -> Result<(), Box<dyn std::error::Error>> {
+    /*
+     * If the latest file can't be fetched then a previously recorded copy
+     * is better than nothing, but make sure it is noticed.
+     */
+    if let Err(e) = refresh(cfg, db) {
+        if db.get_vulnerability_source()?.is_none() {
+            eprintln!("ERROR: Unable to load pkg-vulnerabilities: {}", e);
+            std::process::exit(EXIT_FAILED);
+        }
+        eprintln!(
+            "WARNING: Unable to refresh pkg-vulnerabilities, using the \
+             previous copy: {}",
+            e
+        );
+    }
+
+    let vulns = Vulnerabilities::load(db)?;
+    let mut vulnerable = 0;
+    for prefix in cfg.prefixes() {
+        let available = select::all_remote_pkglist(cfg, db, prefix.path())?;
+        for pkg in db.get_local_pkglist_by_prefix(prefix.path())? {
+            let found = vulns.matching(pkg.pkgname());
+            if found.is_empty() {
+                continue;
+            }
+            vulnerable += 1;
+            for (_, vtype, url) in found {
+                println!(
+                    "Package {} in {} has a {} vulnerability, see {}",
+                    pkg.pkgname(),
+                    prefix.path(),
+                    vtype,
+                    url
+                );
+            }
+            match find_fix(&vulns, pkg.pkgname(), &available) {
+                Some(fix) => println!(
+                    "    {} from {} is not vulnerable",
+                    fix.pkgname(),
+                    fix.repository()
+                ),
+                None => println!(
+                    "    No fixed version of {} is available from any \
+                     repository",
+                    pkg.pkgname()
+                ),
+            }
+        }
+    }
+
+    if vulnerable > 0 {
+        std::process::exit(EXIT_VULNERABLE);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA512: &str = concat!(
+        "6549b210322daf5b4df445f1ca5d41f3608134df9c438e5bde401fea7ceb3812",
+        "ecf57b42066d1162bb44f33103c1057cf643c8daa5483b2903b7a356b681d5f7"
+    );
+
+    fn vulns(body: &str, checksum: &str) -> String {
+        format!("#FORMAT 1.0.0\n#CHECKSUM {}\n#\n{}", checksum, body)
+    }
+
+    fn entry(pattern: &str) -> Vulnerability {
+        (
+            pattern.to_string(),
+            "buffer-overflow".to_string(),
+            "https://example.org/1".to_string(),
+        )
+    }
+
+    fn patterns(v: &Vulnerabilities, pkgname: &str) -> Vec<String> {
+        v.matching(pkgname).iter().map(|e| e.0.clone()).collect()
+    }
+
+    #[test]
+    fn checksum() {
+        let body = "foo<1.5\tbuffer-overflow\thttps://example.org/1\n";
+        let sha512 = format!("SHA512 {}", SHA512);
+        assert_eq!(
+            verify_checksum(&vulns(body, &sha512)),
+            Ok(format!("SHA512:{}", SHA512))
+        );
+        let upper = format!("SHA512 {}", SHA512.to_uppercase());
+        assert_eq!(
+            verify_checksum(&vulns(body, &upper)),
+            Ok(format!("SHA512:{}", SHA512))
+        );
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let body = "foo<1.6\tbuffer-overflow\thttps://example.org/1\n";
+        let sha512 = format!("SHA512 {}", SHA512);
+        assert_eq!(
+            verify_checksum(&vulns(body, &sha512)),
+            Err("checksum mismatch".to_string())
+        );
+        let body = "foo<1.5\tbuffer-overflow\thttps://example.org/1\n";
+        let sha256 = format!("SHA256 {}", &SHA512[..64]);
+        assert_eq!(
+            verify_checksum(&vulns(body, &sha256)),
+            Err("checksum mismatch".to_string())
+        );
+    }
+
+    #[test]
+    fn checksum_invalid() {
+        assert_eq!(
+            verify_checksum("#FORMAT 1.0.0\nfoo<1.5\tcve\turl\n"),
+            Err("missing #CHECKSUM".to_string())
+        );
+        assert_eq!(
+            verify_checksum(&vulns("", "SHA512")),
+            Err("invalid #CHECKSUM".to_string())
+        );
+        assert_eq!(
+            verify_checksum(&vulns("", "NOSUCH 00")),
+            Err("unsupported checksum algorithm NOSUCH".to_string())
+        );
+    }
+
+    #[test]
+    fn signature() {
+        let signed = concat!(
+            "-----BEGIN PGP SIGNED MESSAGE-----\n",
+            "Hash: SHA512\n",
+            "\n",
+            "#FORMAT 1.0.0\n",
+            "- -----BEGIN NOT A SIGNATURE-----\n",
+            "-----BEGIN PGP SIGNATURE-----\n",
+            "\n",
+            "-----END PGP SIGNATURE-----\n"
+        );
+        assert_eq!(
+            strip_signature(signed),
+            Ok("#FORMAT 1.0.0\n-----BEGIN NOT A SIGNATURE-----\n".to_string())
+        );
+        assert_eq!(
+            strip_signature(&signed[..signed.find("#FORMAT").unwrap()]),
+            Err("missing PGP signature".to_string())
+        );
+    }
+
+    #[test]
+    fn alternates() {
+        let v = Vulnerabilities::new(vec![
+            entry("{foo,bar}<1.5"),
+            entry("py{27,37}-{baz,qux}>=2.0<2.1"),
+            entry("foo-1.2"),
+        ]);
+        assert_eq!(patterns(&v, "foo-1.2"), ["{foo,bar}<1.5", "foo-1.2"]);
+        assert_eq!(patterns(&v, "bar-1.4nb2"), ["{foo,bar}<1.5"]);
+        assert!(v.matching("bar-1.5").is_empty());
+        assert!(v.matching("foobar-1.0").is_empty());
+        assert!(v.matching("baz-1.0").is_empty());
+        assert_eq!(
+            patterns(&v, "py37-qux-2.0.3"),
+            ["py{27,37}-{baz,qux}>=2.0<2.1"]
+        );
+        assert!(v.matching("py38-qux-2.0.3").is_empty());
+        assert!(v.matching("py27-baz-2.1").is_empty());
+    }
+
+    #[test]
+    fn wildcard_prefix() {
+        let v = Vulnerabilities::new(vec![
+            entry("*-foo-[0-9]*"),
+            entry("py*-{foo,bar}-1.[0-4]"),
+            entry("foo<2"),
+        ]);
+        assert_eq!(patterns(&v, "py37-foo-1.5"), ["*-foo-[0-9]*"]);
+        assert_eq!(
+            patterns(&v, "py37-foo-1.0"),
+            ["*-foo-[0-9]*", "py*-{foo,bar}-1.[0-4]"]
+        );
+        assert_eq!(patterns(&v, "py27-bar-1.3"), ["py*-{foo,bar}-1.[0-4]"]);
+        assert_eq!(patterns(&v, "foo-1.0"), ["foo<2"]);
+        assert!(v.matching("py37-foobar-1.0").is_empty());
+        assert!(v.matching("py27-bar-1.5").is_empty());
+        assert!(v.is_vulnerable("p5-foo-3.0"));
+        assert!(!v.is_vulnerable("p5-foo"));
+    }
+}
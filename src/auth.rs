@@ -0,0 +1,221 @@
+/*
+ * Copyright (c) 2019 Jonathan Perkin <jonathan@perkin.org.uk>
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ *
+ * auth.rs - credentials for repositories that require authentication.
+ */
+
+use crate::config;
+use std::fs;
+use std::path::PathBuf;
+
+/*
+ * Credentials to send with each request to a repository.  These are kept
+ * entirely separate from the repository URL, which is the identity that is
+ * printed and stored in the database.
+ */
+pub enum Credentials {
+    Basic {
+        username: String,
+        password: Option<String>,
+    },
+    Bearer(String),
+}
+
+/*
+ * Read a secret from a file, ignoring any trailing newline.
+ */
+fn read_secret(path: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let secret =
+        fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    Ok(secret.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+fn read_env(var: &str) -> Result<String, Box<dyn std::error::Error>> {
+    std::env::var(var)
+        .map_err(|_| format!("environment variable {} is not set", var).into())
+}
+
+/*
+ * Return the host part of a URL, without any credentials or port.
+ */
+fn url_host(url: &str) -> Option<&str> {
+    let start = url.find("://")? + 3;
+    let authority = url[start..].split('/').next()?.rsplit('@').next()?;
+    if authority.starts_with('[') {
+        return authority.get(1..authority.find(']')?);
+    }
+    authority.split(':').next()
+}
+
+fn netrc_path() -> Option<PathBuf> {
+    match std::env::var_os("NETRC") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => dirs::home_dir().map(|home| home.join(".netrc")),
+    }
+}
+
+/*
+ * Look up the login and password for a host in a netrc(5) file.  A
+ * "default" entry is used if there is no matching "machine".  Macro
+ * definitions are skipped.
+ */
+fn netrc_lookup(contents: &str, host: &str) -> Option<(String, String)> {
+    let mut found: Option<(String, String)> = None;
+    let mut default: Option<(String, String)> = None;
+    let mut current: Option<bool> = None;
+    let mut login = String::new();
+    let mut password = String::new();
+
+    let mut lines = contents.lines();
+    let mut tokens: Vec<String> = vec![];
+    while let Some(line) = lines.next() {
+        let mut words = line.split_whitespace();
+        while let Some(word) = words.next() {
+            if word == "macdef" {
+                /* Skip the macro body, terminated by an empty line. */
+                for l in lines.by_ref() {
+                    if l.trim().is_empty() {
+                        break;
+                    }
+                }
+                break;
+            }
+            tokens.push(word.to_string());
+            if word == "login" || word == "password" || word == "account" {
+                if let Some(value) = words.next() {
+                    tokens.push(value.to_string());
+                }
+            }
+        }
+    }
+
+    /*
+     * Each "machine" or "default" token closes off the previous entry.
+     */
+    let mut save =
+        |current: Option<bool>, login: &str, password: &str| match current {
+            Some(true) if found.is_none() => {
+                found = Some((login.to_string(), password.to_string()))
+            }
+            Some(false) if default.is_none() => {
+                default = Some((login.to_string(), password.to_string()))
+            }
+            _ => {}
+        };
+    let mut tokens = tokens.iter();
+    while let Some(token) = tokens.next() {
+        match token.as_str() {
+            "machine" => {
+                save(current, &login, &password);
+                let machine = tokens.next().map(String::as_str).unwrap_or("");
+                current = if machine.eq_ignore_ascii_case(host) {
+                    Some(true)
+                } else {
+                    None
+                };
+                login.clear();
+                password.clear();
+            }
+            "default" => {
+                save(current, &login, &password);
+                current = Some(false);
+                login.clear();
+                password.clear();
+            }
+            "login" => {
+                login = tokens.next().cloned().unwrap_or_default();
+            }
+            "password" => {
+                password = tokens.next().cloned().unwrap_or_default();
+            }
+            _ => {}
+        }
+    }
+    save(current, &login, &password);
+
+    found.or(default)
+}
+
+/*
+ * Return the credentials to use for a request to a repository, if any.
+ * Explicitly configured credentials are used first, but only for the
+ * repository's own host, as files such as the digest and signature may be
+ * elsewhere.  Otherwise the user's netrc file is searched for the host of
+ * the request.
+ */
+pub fn credentials(
+    repo: &config::Repository,
+    url: &str,
+) -> Result<Option<Credentials>, Box<dyn std::error::Error>> {
+    let host = match url_host(url) {
+        Some(host) => host,
+        None => return Ok(None),
+    };
+    let same_host =
+        url_host(repo.url()).is_some_and(|h| h.eq_ignore_ascii_case(host));
+    if same_host {
+        if let Some(creds) = configured_credentials(repo)? {
+            return Ok(Some(creds));
+        }
+    }
+    Ok(netrc_credentials(host))
+}
+
+/*
+ * Return the credentials configured for a repository, if any.
+ */
+fn configured_credentials(
+    repo: &config::Repository,
+) -> Result<Option<Credentials>, Box<dyn std::error::Error>> {
+    if let Some(token) = repo.token() {
+        return Ok(Some(Credentials::Bearer(token.clone())));
+    }
+    if let Some(var) = repo.token_env() {
+        return Ok(Some(Credentials::Bearer(read_env(var)?)));
+    }
+    if let Some(path) = repo.token_file() {
+        return Ok(Some(Credentials::Bearer(read_secret(path)?)));
+    }
+
+    if let Some(username) = repo.username() {
+        let password = if let Some(password) = repo.password() {
+            Some(password.clone())
+        } else if let Some(var) = repo.password_env() {
+            Some(read_env(var)?)
+        } else if let Some(path) = repo.password_file() {
+            Some(read_secret(path)?)
+        } else {
+            None
+        };
+        return Ok(Some(Credentials::Basic {
+            username: username.clone(),
+            password,
+        }));
+    }
+
+    Ok(None)
+}
+
+/*
+ * Return the credentials for a host from the user's netrc file, if any.
+ */
+fn netrc_credentials(host: &str) -> Option<Credentials> {
+    let contents = fs::read_to_string(netrc_path()?).ok()?;
+    let (username, password) = netrc_lookup(&contents, host)?;
+    Some(Credentials::Basic {
+        username,
+        password: Some(password),
+    })
+}
@@ -0,0 +1,196 @@
+/*
+ * Copyright (c) 2019 Jonathan Perkin <jonathan@perkin.org.uk>
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ *
+ * changes.rs - work out what changed in a repository between updates.
+ */
+
+use crate::dewey;
+use crate::list::PackageList;
+use pkgsrc::SummaryEntry;
+use serde_derive::Serialize;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Debug, Serialize)]
+pub struct Package {
+    pkgbase: String,
+    version: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VersionChange {
+    pkgbase: String,
+    old_version: String,
+    new_version: String,
+}
+
+/*
+ * Everything that changed in a single repository.  This is serialised as-is
+ * for "pm update --changes-json".
+ */
+#[derive(Debug, Default, Serialize)]
+pub struct Changes {
+    repository: String,
+    url: String,
+    prefix: String,
+    added: Vec<Package>,
+    removed: Vec<Package>,
+    upgraded: Vec<VersionChange>,
+    downgraded: Vec<VersionChange>,
+    rebuilt: Vec<Package>,
+}
+
+impl Changes {
+    /*
+     * Compare the packages previously recorded for a repository against the
+     * new pkg_summary.  Packages are matched up by PKGBASE, and as a
+     * repository may carry several versions of the same PKGBASE, versions
+     * that have gone and versions that have appeared are paired off oldest
+     * first.  Anything left over is a straight addition or removal.
+     */
+    pub fn new(
+        repository: &str,
+        url: &str,
+        prefix: &str,
+        old: &[PackageList],
+        new: &[SummaryEntry],
+    ) -> Changes {
+        let mut changes = Changes {
+            repository: repository.to_string(),
+            url: url.to_string(),
+            prefix: prefix.to_string(),
+            ..Default::default()
+        };
+
+        /*
+         * PKGBASE -> (version -> BUILD_DATE), for the old and new sets.
+         */
+        type Versions<'a> = BTreeMap<&'a str, HashMap<&'a str, &'a str>>;
+        let mut oldpkgs: Versions = BTreeMap::new();
+        let mut newpkgs: Versions = BTreeMap::new();
+        for p in old {
+            oldpkgs
+                .entry(p.pkgbase())
+                .or_default()
+                .insert(p.pkgversion(), p.build_date());
+        }
+        for p in new {
+            newpkgs
+                .entry(p.pkgbase())
+                .or_default()
+                .insert(p.pkgversion(), p.build_date());
+        }
+
+        let empty = HashMap::new();
+        let mut pkgbases: Vec<&str> =
+            oldpkgs.keys().chain(newpkgs.keys()).cloned().collect();
+        pkgbases.sort_unstable();
+        pkgbases.dedup();
+
+        for pkgbase in pkgbases {
+            let oldvers = oldpkgs.get(pkgbase).unwrap_or(&empty);
+            let newvers = newpkgs.get(pkgbase).unwrap_or(&empty);
+            let sort = |vers: &mut Vec<&str>| {
+                vers.sort_by(|a, b| dewey::compare(a, b))
+            };
+
+            let mut gone: Vec<&str> = oldvers
+                .keys()
+                .filter(|v| !newvers.contains_key(*v))
+                .cloned()
+                .collect();
+            let mut appeared: Vec<&str> = newvers
+                .keys()
+                .filter(|v| !oldvers.contains_key(*v))
+                .cloned()
+                .collect();
+            let mut kept: Vec<&str> = oldvers
+                .keys()
+                .filter(|v| newvers.contains_key(*v))
+                .cloned()
+                .collect();
+            sort(&mut gone);
+            sort(&mut appeared);
+            sort(&mut kept);
+
+            /*
+             * BUILD_DATE is "YYYY-MM-DD HH:MM:SS +ZZZZ" so a string
+             * comparison is good enough to tell if it is newer.
+             */
+            for v in kept {
+                if newvers[v] > oldvers[v] {
+                    changes.rebuilt.push(Package {
+                        pkgbase: pkgbase.to_string(),
+                        version: v.to_string(),
+                    });
+                }
+            }
+
+            let paired = gone.len().min(appeared.len());
+            for (o, n) in gone.iter().zip(appeared.iter()) {
+                let change = VersionChange {
+                    pkgbase: pkgbase.to_string(),
+                    old_version: o.to_string(),
+                    new_version: n.to_string(),
+                };
+                match dewey::compare(n, o) {
+                    Ordering::Less => changes.downgraded.push(change),
+                    _ => changes.upgraded.push(change),
+                }
+            }
+            for v in &gone[paired..] {
+                changes.removed.push(Package {
+                    pkgbase: pkgbase.to_string(),
+                    version: v.to_string(),
+                });
+            }
+            for v in &appeared[paired..] {
+                changes.added.push(Package {
+                    pkgbase: pkgbase.to_string(),
+                    version: v.to_string(),
+                });
+            }
+        }
+
+        changes
+    }
+
+    /*
+     * Print the changes in a form suitable for humans, one package per line.
+     */
+    pub fn print(&self) {
+        for p in &self.added {
+            println!("  added       {}-{}", p.pkgbase, p.version);
+        }
+        for p in &self.removed {
+            println!("  removed     {}-{}", p.pkgbase, p.version);
+        }
+        for c in &self.upgraded {
+            println!(
+                "  upgraded    {}-{} -> {}",
+                c.pkgbase, c.old_version, c.new_version
+            );
+        }
+        for c in &self.downgraded {
+            println!(
+                "  downgraded  {}-{} -> {}",
+                c.pkgbase, c.old_version, c.new_version
+            );
+        }
+        for p in &self.rebuilt {
+            println!("  rebuilt     {}-{}", p.pkgbase, p.version);
+        }
+    }
+}
@@ -16,11 +16,14 @@
  * config.rs - handle loading/parsing of pm(1) config files written in TOML.
  */
 
+use crate::output;
 use crate::OptArgs;
 use serde_derive::Deserialize;
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
 use std::process::Command;
+use std::time::Duration;
 
 extern crate dirs;
 extern crate toml;
@@ -33,9 +36,33 @@ pub struct Config {
     filename: PathBuf,
     prefix: String,
     prefixes: Vec<Prefix>,
+    repo: Option<String>,
+    output: output::Format,
+    template: Option<output::Template>,
+    proxy: Option<String>,
+    no_proxy: Vec<String>,
+    pins: Vec<Pin>,
+    vulnerabilities: Repository,
+    vulnerabilities_configured: bool,
+    vulnerabilities_keyring: Option<String>,
     verbose: bool,
 }
 
+/*
+ * Default transfer settings, used when neither the repository nor the global
+ * configuration specify a value.  Timeouts are in seconds.
+ */
+const DEFAULT_CONNECT_TIMEOUT: u64 = 30;
+const DEFAULT_READ_TIMEOUT: u64 = 300;
+const DEFAULT_RETRIES: u32 = 3;
+const DEFAULT_RETRY_BACKOFF: u64 = 1;
+
+/*
+ * Where to fetch pkg-vulnerabilities from if not configured.
+ */
+const DEFAULT_VULNERABILITIES: &str =
+    "https://cdn.NetBSD.org/pub/NetBSD/packages/vulns/pkg-vulnerabilities.gz";
+
 /*
  * Struct used for deserializing from the TOML configuration file, this is
  * parsed into Config for use throughout the program.
@@ -44,7 +71,16 @@ pub struct Config {
 struct ConfigFile {
     default_prefix: Option<String>,
     verbose: Option<bool>,
+    connect_timeout: Option<u64>,
+    read_timeout: Option<u64>,
+    retries: Option<u32>,
+    retry_backoff: Option<u64>,
+    proxy: Option<String>,
+    no_proxy: Option<Vec<String>>,
+    vulnerabilities: Option<String>,
+    vulnerabilities_keyring: Option<String>,
     prefix: Option<Vec<Prefix>>,
+    pin: Option<Vec<Pin>>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -53,17 +89,59 @@ pub struct Prefix {
     pkg_admin: Option<String>,
     pkg_info: Option<String>,
     pkgdb: Option<String>,
+    verify_signatures: Option<bool>,
+    gpg: Option<String>,
+    gpg_keyring: Option<String>,
+    acceptable_licenses: Option<Vec<String>>,
+    acceptable_licenses_from: Option<String>,
+    variables: Option<HashMap<String, String>>,
     repository: Option<Vec<Repository>>,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize)]
 pub struct Repository {
     url: String,
     name: Option<String>,
+    enabled: Option<bool>,
+    priority: Option<i64>,
     summary_extension: Option<String>,
+    strict: Option<bool>,
+    public_key: Option<String>,
+    public_key_file: Option<String>,
+    signature: Option<String>,
+    digest: Option<String>,
+    connect_timeout: Option<u64>,
+    read_timeout: Option<u64>,
+    retries: Option<u32>,
+    retry_backoff: Option<u64>,
+    ca_bundle: Option<String>,
+    client_cert: Option<String>,
+    client_key: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    password_env: Option<String>,
+    password_file: Option<String>,
+    token: Option<String>,
+    token_env: Option<String>,
+    token_file: Option<String>,
+}
+
+/*
+ * Pin packages whose PKGBASE matches the "pkg" glob to a named repository,
+ * optionally restricted to versions matching "version".
+ */
+#[derive(Clone, Debug, Deserialize)]
+pub struct Pin {
+    pkg: String,
+    repository: String,
+    version: Option<String>,
 }
 
 impl Config {
+    pub fn filename(&self) -> &PathBuf {
+        &self.filename
+    }
+
     pub fn prefix(&self) -> &str {
         &self.prefix
     }
@@ -72,12 +150,76 @@ impl Config {
         &self.prefixes
     }
 
+    /*
+     * Repository chosen with --repo to restrict queries to.
+     */
+    pub fn repo(&self) -> &Option<String> {
+        &self.repo
+    }
+
+    /*
+     * Output format chosen with --output.
+     */
+    pub fn output(&self) -> output::Format {
+        self.output
+    }
+
+    /*
+     * Template chosen with --format.
+     */
+    pub fn template(&self) -> &Option<output::Template> {
+        &self.template
+    }
+
+    pub fn proxy(&self) -> &Option<String> {
+        &self.proxy
+    }
+
+    pub fn no_proxy(&self) -> &Vec<String> {
+        &self.no_proxy
+    }
+
+    pub fn pins(&self) -> &Vec<Pin> {
+        &self.pins
+    }
+
+    /*
+     * The pkg-vulnerabilities file, as a repository so that it can be
+     * fetched with the global transfer settings.  Its URL may also be a
+     * local path.
+     */
+    pub fn vulnerabilities(&self) -> &Repository {
+        &self.vulnerabilities
+    }
+
+    /*
+     * Whether pkg-vulnerabilities was explicitly configured, rather than
+     * using the default.
+     */
+    pub fn vulnerabilities_configured(&self) -> bool {
+        self.vulnerabilities_configured
+    }
+
+    /*
+     * Keyring used to verify a signed pkg-vulnerabilities file.
+     */
+    pub fn vulnerabilities_keyring(&self) -> &Option<String> {
+        &self.vulnerabilities_keyring
+    }
+
+    /*
+     * Return the configuration for a prefix, if it is configured.
+     */
+    pub fn get_prefix(&self, path: &str) -> Option<&Prefix> {
+        self.prefixes.iter().find(|p| p.path() == path)
+    }
+
     #[allow(dead_code)]
     pub fn verbose(&self) -> bool {
         self.verbose
     }
 
-    pub fn load(argv: &OptArgs) -> Result<Config, std::io::Error> {
+    pub fn load(argv: &OptArgs) -> Result<Config, Box<dyn std::error::Error>> {
         /*
          * Start with an empty Config, then populate it based on the input
          * from the user pm.toml.
@@ -86,6 +228,15 @@ impl Config {
             filename: PathBuf::new(),
             prefix: String::new(),
             prefixes: vec![],
+            repo: None,
+            output: argv.output,
+            template: argv.format.clone(),
+            proxy: None,
+            no_proxy: vec![],
+            pins: vec![],
+            vulnerabilities: Repository::default(),
+            vulnerabilities_configured: false,
+            vulnerabilities_keyring: None,
             verbose: false,
         };
 
@@ -98,22 +249,23 @@ impl Config {
             dirs::config_dir().unwrap().join("pm.toml")
         };
         if !config.filename.exists() {
-            eprintln!(
-                "ERROR: Configuration file {} does not exist",
+            return Err(format!(
+                "Configuration file {} does not exist",
                 config.filename.display()
-            );
-            std::process::exit(1);
+            )
+            .into());
         }
 
         let cfgfile: ConfigFile =
-            toml::from_str(&fs::read_to_string(&config.filename)?).unwrap();
+            toml::from_str(&fs::read_to_string(&config.filename)?)
+                .map_err(|e| format!("{}: {}", config.filename.display(), e))?;
 
         /*
          * Validate and insert configured prefixes.  Save the first prefix to
          * use as the default if not otherwise specified.
          */
         let mut first_prefix: Option<String> = None;
-        if let Some(prefixes) = cfgfile.prefix {
+        if let Some(prefixes) = &cfgfile.prefix {
             for prefix in prefixes {
                 let mut p = prefix.clone();
                 first_prefix.get_or_insert(p.path().to_string());
@@ -130,22 +282,52 @@ impl Config {
                     );
                     continue;
                 }
+
                 /*
                  * Calculate PKG_DBDIR from pkg_admin(1) if not specified.
                  */
                 if p.pkgdb.is_none() {
                     let pkgdb = Command::new(p.pkg_admin())
-                        .args(&["config-var", "PKG_DBDIR"])
+                        .args(["config-var", "PKG_DBDIR"])
                         .output()
                         .expect("could not execute pkg_admin");
                     let pkgdb =
                         std::str::from_utf8(&pkgdb.stdout).unwrap().trim();
                     p.pkgdb = Some(pkgdb.to_string());
                 }
+                p.expand_urls()?;
+
+                /*
+                 * Any transfer settings not specified for a repository are
+                 * inherited from the global settings, or the defaults.
+                 */
+                if let Some(repos) = p.repository.as_mut() {
+                    for r in repos {
+                        r.strip_userinfo();
+                        r.set_transfer_defaults(&cfgfile);
+                    }
+                }
                 config.prefixes.push(p);
             }
         }
 
+        /*
+         * pkg-vulnerabilities is fetched using the global transfer settings.
+         */
+        config.vulnerabilities = Repository {
+            url: cfgfile
+                .vulnerabilities
+                .clone()
+                .unwrap_or_else(|| DEFAULT_VULNERABILITIES.to_string()),
+            name: Some("pkg-vulnerabilities".to_string()),
+            ..Default::default()
+        };
+        config.vulnerabilities_configured = cfgfile.vulnerabilities.is_some();
+        config.vulnerabilities.strip_userinfo();
+        config.vulnerabilities.set_transfer_defaults(&cfgfile);
+        config.vulnerabilities_keyring =
+            cfgfile.vulnerabilities_keyring.clone();
+
         /*
          * Set the default prefix to use for operations.
          */
@@ -157,6 +339,28 @@ impl Config {
             config.prefix = p.clone();
         }
 
+        config.repo = argv.repo.clone();
+
+        /*
+         * Proxy settings in the configuration file take precedence over the
+         * standard environment variables, which are otherwise used as-is.
+         */
+        config.proxy = cfgfile.proxy;
+        config.no_proxy = match cfgfile.no_proxy {
+            Some(np) => np,
+            None => std::env::var("no_proxy")
+                .or_else(|_| std::env::var("NO_PROXY"))
+                .map(|np| {
+                    np.split(',')
+                        .map(|h| h.trim().to_string())
+                        .filter(|h| !h.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+        };
+
+        config.pins = cfgfile.pin.unwrap_or_default();
+
         config.verbose = argv.verbose || cfgfile.verbose.unwrap_or(false);
 
         Ok(config)
@@ -168,31 +372,351 @@ impl Prefix {
         &self.path
     }
 
+    /*
+     * Look up the value of a variable for use in repository URLs.  Values
+     * set for the prefix in the configuration file win, then whatever
+     * pkg_admin(1) reports, falling back to uname(1) for the variables that
+     * have a sensible equivalent.
+     */
+    fn variable(&self, name: &str) -> Option<String> {
+        if let Some(value) = self.variables.as_ref().and_then(|v| v.get(name)) {
+            return Some(value.clone());
+        }
+        let output = |cmd: &str, args: &[&str]| -> Option<String> {
+            let out = Command::new(cmd).args(args).output().ok()?;
+            let value = std::str::from_utf8(&out.stdout).ok()?.trim();
+            if out.status.success() && !value.is_empty() {
+                Some(value.to_string())
+            } else {
+                None
+            }
+        };
+        if let Some(value) = output(self.pkg_admin(), &["config-var", name]) {
+            return Some(value);
+        }
+        match name {
+            "OPSYS" => output("uname", &["-s"]),
+            "MACHINE_ARCH" => output("uname", &["-m"]),
+            "OS_VERSION" => output("uname", &["-r"]),
+            _ => None,
+        }
+    }
+
+    /*
+     * Expand ${VARIABLE} references in repository URLs.  Each variable is
+     * only looked up once per prefix.  An undefined variable is a fatal
+     * configuration error, as the resulting URL would be meaningless.
+     */
+    fn expand_urls(&mut self) -> Result<(), String> {
+        let mut cache: HashMap<String, String> = HashMap::new();
+        let mut repos = match self.repository.take() {
+            Some(repos) => repos,
+            None => return Ok(()),
+        };
+        for repo in &mut repos {
+            let mut url = String::new();
+            let mut rest = repo.url.as_str();
+            while let Some(start) = rest.find("${") {
+                let end = match rest[start..].find('}') {
+                    Some(end) => start + end,
+                    None => break,
+                };
+                let name = &rest[start + 2..end];
+                if !cache.contains_key(name) {
+                    match self.variable(name) {
+                        Some(value) => {
+                            cache.insert(name.to_string(), value);
+                        }
+                        None => {
+                            return Err(format!(
+                                "Undefined variable ${{{}}} in {}",
+                                name, repo.url
+                            ));
+                        }
+                    }
+                }
+                url.push_str(&rest[..start]);
+                url.push_str(&cache[name]);
+                rest = &rest[end + 1..];
+            }
+            url.push_str(rest);
+            repo.url = url;
+        }
+        self.repository = Some(repos);
+        Ok(())
+    }
+
     /*
      * These are all safe to unwrap as they are checked during the loading of
      * the configuration prior to use.
      */
     pub fn pkg_admin(&self) -> &str {
-        &self.pkg_admin.as_ref().unwrap()
+        self.pkg_admin.as_ref().unwrap()
     }
     pub fn pkg_info(&self) -> &str {
-        &self.pkg_info.as_ref().unwrap()
+        self.pkg_info.as_ref().unwrap()
     }
     pub fn pkgdb(&self) -> &str {
-        &self.pkgdb.as_ref().unwrap()
+        self.pkgdb.as_ref().unwrap()
+    }
+
+    /*
+     * Whether packages for this prefix must be signed.
+     */
+    pub fn verify_signatures(&self) -> bool {
+        self.verify_signatures.unwrap_or(false)
+    }
+
+    /*
+     * gpg(1) and the keyring of trusted keys used to verify signed packages,
+     * defaulting to the pkg_install settings for the prefix.
+     */
+    pub fn gpg(&self) -> String {
+        self.gpg
+            .clone()
+            .or_else(|| self.variable("GPG"))
+            .unwrap_or_else(|| "gpg".to_string())
+    }
+    pub fn gpg_keyring(&self) -> Option<String> {
+        self.gpg_keyring
+            .clone()
+            .or_else(|| self.variable("GPG_KEYRING_VERIFY"))
+    }
+
+    /*
+     * Licenses accepted for the prefix, if a policy has been set, and where
+     * else to read ACCEPTABLE_LICENSES from.
+     */
+    pub fn acceptable_licenses(&self) -> &Option<Vec<String>> {
+        &self.acceptable_licenses
+    }
+    pub fn acceptable_licenses_from(&self) -> &Option<String> {
+        &self.acceptable_licenses_from
+    }
+
+    /*
+     * ACCEPTABLE_LICENSES as set in pkg_install.conf.
+     */
+    pub fn pkg_install_licenses(&self) -> Option<String> {
+        self.variable("ACCEPTABLE_LICENSES")
     }
 
     pub fn repositories(&self) -> &Option<Vec<Repository>> {
         &self.repository
     }
+
+    pub fn enabled_repositories(&self) -> impl Iterator<Item = &Repository> {
+        self.repository.iter().flatten().filter(|r| r.enabled())
+    }
+}
+
+/*
+ * Decode %XX escapes, as used for reserved characters in URL userinfo.
+ */
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+            if let Ok(b) = u8::from_str_radix(hex, 16) {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+/*
+ * Return a URL without any credentials, so that it can be printed.
+ */
+pub fn strip_userinfo(url: &str) -> String {
+    let mut repo = Repository {
+        url: url.to_string(),
+        ..Default::default()
+    };
+    repo.strip_userinfo();
+    repo.url
 }
 
 impl Repository {
+    /*
+     * Credentials embedded in the URL are moved into the username and
+     * password fields, so that the URL can be safely printed and used as the
+     * repository identity in the database.  Explicit settings win.
+     */
+    fn strip_userinfo(&mut self) {
+        let start = match self.url.find("://") {
+            Some(i) => i + 3,
+            None => return,
+        };
+        let end = self.url[start..]
+            .find('/')
+            .map(|i| start + i)
+            .unwrap_or_else(|| self.url.len());
+        let at = match self.url[start..end].rfind('@') {
+            Some(i) => start + i,
+            None => return,
+        };
+        let userinfo = self.url[start..at].to_string();
+        self.url.replace_range(start..=at, "");
+        let mut userinfo = userinfo.splitn(2, ':');
+        if let Some(user) = userinfo.next() {
+            self.username.get_or_insert(percent_decode(user));
+        }
+        if let Some(pass) = userinfo.next() {
+            self.password.get_or_insert(percent_decode(pass));
+        }
+    }
+
+    /*
+     * Fill in transfer settings from the global settings or the defaults.
+     */
+    fn set_transfer_defaults(&mut self, cfgfile: &ConfigFile) {
+        self.connect_timeout.get_or_insert(
+            cfgfile.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT),
+        );
+        self.read_timeout.get_or_insert(
+            cfgfile.read_timeout.unwrap_or(DEFAULT_READ_TIMEOUT),
+        );
+        self.retries
+            .get_or_insert(cfgfile.retries.unwrap_or(DEFAULT_RETRIES));
+        self.retry_backoff.get_or_insert(
+            cfgfile.retry_backoff.unwrap_or(DEFAULT_RETRY_BACKOFF),
+        );
+    }
+
     pub fn url(&self) -> &String {
         &self.url
     }
 
+    pub fn name(&self) -> &Option<String> {
+        &self.name
+    }
+
+    /*
+     * How the repository is identified to the user and in the database,
+     * using its name if it has one, otherwise its URL.
+     */
+    pub fn label(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.url)
+    }
+
+    /*
+     * Disabled repositories are kept in the configuration but otherwise
+     * ignored, as if they were not configured at all.
+     */
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
+    /*
+     * When a package is available from several repositories, the copy from
+     * the repository with the highest priority wins.
+     */
+    pub fn priority(&self) -> i64 {
+        self.priority.unwrap_or(0)
+    }
+
     pub fn summary_extension(&self) -> &Option<String> {
         &self.summary_extension
     }
+
+    /*
+     * A strict repository has its whole pkg_summary rejected if any entry is
+     * invalid, otherwise invalid entries are skipped with a warning.
+     */
+    pub fn strict(&self) -> bool {
+        self.strict.unwrap_or(false)
+    }
+
+    /*
+     * A public key, either inline or in a file, requires every pkg_summary
+     * to be signed.  "signature" overrides where the signature is fetched
+     * from.
+     */
+    pub fn public_key(&self) -> &Option<String> {
+        &self.public_key
+    }
+    pub fn public_key_file(&self) -> &Option<String> {
+        &self.public_key_file
+    }
+    pub fn signature(&self) -> &Option<String> {
+        &self.signature
+    }
+
+    /*
+     * A file of SHA512 or SHA256 digests that each pkg_summary must match.
+     */
+    pub fn digest(&self) -> &Option<String> {
+        &self.digest
+    }
+
+    /*
+     * As with Prefix, these are filled in during configuration loading and
+     * are safe to unwrap.
+     */
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_secs(self.connect_timeout.unwrap())
+    }
+    pub fn read_timeout(&self) -> Duration {
+        Duration::from_secs(self.read_timeout.unwrap())
+    }
+    pub fn retries(&self) -> u32 {
+        self.retries.unwrap()
+    }
+    pub fn retry_backoff(&self) -> Duration {
+        Duration::from_secs(self.retry_backoff.unwrap())
+    }
+
+    pub fn ca_bundle(&self) -> &Option<String> {
+        &self.ca_bundle
+    }
+    pub fn client_cert(&self) -> &Option<String> {
+        &self.client_cert
+    }
+    pub fn client_key(&self) -> &Option<String> {
+        &self.client_key
+    }
+
+    pub fn username(&self) -> &Option<String> {
+        &self.username
+    }
+    pub fn password(&self) -> &Option<String> {
+        &self.password
+    }
+    pub fn password_env(&self) -> &Option<String> {
+        &self.password_env
+    }
+    pub fn password_file(&self) -> &Option<String> {
+        &self.password_file
+    }
+    pub fn token(&self) -> &Option<String> {
+        &self.token
+    }
+    pub fn token_env(&self) -> &Option<String> {
+        &self.token_env
+    }
+    pub fn token_file(&self) -> &Option<String> {
+        &self.token_file
+    }
+}
+
+impl Pin {
+    pub fn pkg(&self) -> &str {
+        &self.pkg
+    }
+
+    pub fn repository(&self) -> &str {
+        &self.repository
+    }
+
+    pub fn version(&self) -> &Option<String> {
+        &self.version
+    }
 }
@@ -0,0 +1,71 @@
+/*
+ * Copyright (c) 2019 Jonathan Perkin <jonathan@perkin.org.uk>
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ *
+ * db.rs - handle "pm db" commands.
+ */
+
+use crate::pmdb::{MIGRATIONS, PMDB};
+use std::path::Path;
+
+/*
+ * Show which migrations have been applied, without touching the database.
+ */
+fn status(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let version = PMDB::schema_version_at(path)?;
+    let latest = PMDB::latest_version();
+    println!("Database: {}", path.display());
+    match version {
+        None => println!("Version:  not created yet"),
+        Some(v) if v > latest => {
+            println!("Version:  {} (newer than this pm supports)", v)
+        }
+        Some(v) if v == latest => println!("Version:  {} (up to date)", v),
+        Some(v) => println!("Version:  {} ({} pending)", v, latest - v),
+    }
+    for m in MIGRATIONS {
+        let state = if version.is_some_and(|v| v >= m.version) {
+            "applied"
+        } else {
+            "pending"
+        };
+        println!("{:>4}  {:8} {}", m.version, state, m.description);
+    }
+    Ok(())
+}
+
+/*
+ * Apply any pending migrations.  This normally happens automatically when
+ * the database is opened, but can be run on its own.
+ */
+pub fn migrate(
+    path: &Path,
+    show_status: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if show_status {
+        return status(path);
+    }
+    let before = PMDB::schema_version_at(path)?.unwrap_or(0);
+    if let Err(e) = PMDB::new(path) {
+        eprintln!("ERROR: {}", e);
+        std::process::exit(1);
+    }
+    let latest = PMDB::latest_version();
+    if before == latest {
+        println!("Database is up to date at version {}", latest);
+    } else {
+        println!("Migrated database from version {} to {}", before, latest);
+    }
+    Ok(())
+}
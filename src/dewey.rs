@@ -0,0 +1,257 @@
+/*
+ * Copyright (c) 2019 Jonathan Perkin <jonathan@perkin.org.uk>
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ *
+ * dewey.rs - compare package versions following pkg_install's dewey.c.
+ *
+ * The dewey support in the pkgsrc crate only keeps the last digit of each
+ * numeric component, so for example 8.1.2000 compares as older than
+ * 8.1.1004.  Until that is fixed, versions are compared here instead.
+ */
+
+use std::cmp::Ordering;
+
+/*
+ * Modifiers and their values, in the order pkg_install tests them.
+ */
+const MODIFIERS: [(&str, i64); 7] = [
+    ("alpha", -3),
+    ("beta", -2),
+    ("pre", -1),
+    ("rc", -1),
+    ("pl", 0),
+    ("_", 0),
+    (".", 0),
+];
+
+/*
+ * Split a version into its numeric components and any "nb" revision.
+ */
+fn components(version: &str) -> (Vec<i64>, i64) {
+    let digits =
+        |s: &str| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let mut vec = vec![];
+    let mut nb = 0;
+    let mut s = version;
+    'next: while let Some(c) = s.chars().next() {
+        if c.is_ascii_digit() {
+            let end = digits(s);
+            vec.push(s[..end].parse().unwrap_or(i64::MAX));
+            s = &s[end..];
+            continue;
+        }
+        for (m, v) in MODIFIERS.iter() {
+            if s.starts_with(m) {
+                vec.push(*v);
+                s = &s[m.len()..];
+                continue 'next;
+            }
+        }
+        if s.starts_with("nb") {
+            s = &s[2..];
+            let end = digits(s);
+            nb = s[..end].parse().unwrap_or(0);
+            s = &s[end..];
+            continue;
+        }
+        if c.is_ascii_alphabetic() {
+            vec.push(0);
+            vec.push(i64::from(c.to_ascii_lowercase() as u8 - b'a' + 1));
+        }
+        s = &s[c.len_utf8()..];
+    }
+    (vec, nb)
+}
+
+/*
+ * Compare two versions.
+ */
+pub fn compare(a: &str, b: &str) -> Ordering {
+    let (mut a, a_nb) = components(a);
+    let (mut b, b_nb) = components(b);
+    let len = a.len().max(b.len());
+    a.resize(len, 0);
+    b.resize(len, 0);
+    a.cmp(&b).then(a_nb.cmp(&b_nb))
+}
+
+/*
+ * Return whether a version matches a dewey expression without a PKGBASE,
+ * such as ">=1.1<1.2".
+ */
+pub fn matches(pattern: &str, version: &str) -> bool {
+    let mut rest = pattern;
+    let mut matched = false;
+    while !rest.is_empty() {
+        let (ok, oplen): (fn(Ordering) -> bool, usize) =
+            if rest.starts_with(">=") {
+                (|o| o != Ordering::Less, 2)
+            } else if rest.starts_with("<=") {
+                (|o| o != Ordering::Greater, 2)
+            } else if rest.starts_with('>') {
+                (|o| o == Ordering::Greater, 1)
+            } else if rest.starts_with('<') {
+                (|o| o == Ordering::Less, 1)
+            } else {
+                return false;
+            };
+        rest = &rest[oplen..];
+        let end = rest.find(['<', '>']).unwrap_or(rest.len());
+        if !ok(compare(version, &rest[..end])) {
+            return false;
+        }
+        rest = &rest[end..];
+        matched = true;
+    }
+    matched
+}
+
+/*
+ * Expand the first set of csh-style {foo,bar} alternates in a pattern,
+ * returning None if there are none.  Nested alternates are left for the
+ * next expansion.
+ */
+pub fn expand_alternates(pattern: &str) -> Option<Vec<String>> {
+    let open = pattern.find('{')?;
+    let mut depth = 0;
+    let mut alts = vec![];
+    let mut start = open + 1;
+    for (i, c) in pattern[open..].char_indices().map(|(i, c)| (open + i, c)) {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    alts.push(&pattern[start..i]);
+                    return Some(
+                        alts.iter()
+                            .map(|alt| {
+                                format!(
+                                    "{}{}{}",
+                                    &pattern[..open],
+                                    alt,
+                                    &pattern[i + 1..]
+                                )
+                            })
+                            .collect(),
+                    );
+                }
+            }
+            ',' if depth == 1 => {
+                alts.push(&pattern[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/*
+ * Match a package name against a pattern in the same way as pkg_install's
+ * pkg_match(), supporting alternates, dewey comparisons, and globs.
+ */
+pub fn pkg_match(pattern: &str, pkgname: &str) -> bool {
+    if let Some(patterns) = expand_alternates(pattern) {
+        return patterns.iter().any(|p| pkg_match(p, pkgname));
+    }
+    if let Some(i) = pattern.find(['<', '>']) {
+        return match pkgname.rfind('-') {
+            Some(n) => {
+                pattern[..i] == pkgname[..n]
+                    && matches(&pattern[i..], &pkgname[n + 1..])
+            }
+            None => false,
+        };
+    }
+    if pattern.contains(['*', '?', '[']) {
+        return glob::Pattern::new(pattern)
+            .map(|p| p.matches(pkgname))
+            .unwrap_or(false);
+    }
+    pattern == pkgname
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compare_versions() {
+        assert_eq!(compare("1.0", "1.0"), Ordering::Equal);
+        assert_eq!(compare("1.0", "1.0.0"), Ordering::Equal);
+        assert_eq!(compare("1.0", "1.1"), Ordering::Less);
+        assert_eq!(compare("1.10", "1.9"), Ordering::Greater);
+        assert_eq!(compare("8.1.2000", "8.1.1004"), Ordering::Greater);
+        assert_eq!(compare("1.0nb1", "1.0"), Ordering::Greater);
+        assert_eq!(compare("1.0nb2", "1.0nb10"), Ordering::Less);
+        assert_eq!(compare("1.1", "1.0nb10"), Ordering::Greater);
+    }
+
+    #[test]
+    fn compare_modifiers() {
+        assert_eq!(compare("1.0alpha1", "1.0beta1"), Ordering::Less);
+        assert_eq!(compare("1.0beta1", "1.0rc1"), Ordering::Less);
+        assert_eq!(compare("1.0pre1", "1.0rc1"), Ordering::Equal);
+        assert_eq!(compare("1.0rc1", "1.0"), Ordering::Less);
+        assert_eq!(compare("1.0pl1", "1.0.1"), Ordering::Equal);
+        assert_eq!(compare("1.0_1", "1.0.1"), Ordering::Equal);
+        assert_eq!(compare("1.0a", "1.0"), Ordering::Greater);
+        assert_eq!(compare("1.0b", "1.0a"), Ordering::Greater);
+        assert_eq!(compare("1.0B", "1.0b"), Ordering::Equal);
+    }
+
+    #[test]
+    fn matches_ranges() {
+        assert!(matches(">=1.0", "1.0"));
+        assert!(matches(">1.0", "1.0nb1"));
+        assert!(!matches(">1.0", "1.0"));
+        assert!(matches("<=1.0", "1.0"));
+        assert!(!matches("<1.0", "1.0"));
+        assert!(matches(">=1.1<1.2", "1.1.5"));
+        assert!(!matches(">=1.1<1.2", "1.2"));
+        assert!(!matches(">=1.1<1.2", "1.0"));
+        assert!(!matches("", "1.0"));
+        assert!(!matches("1.0", "1.0"));
+    }
+
+    #[test]
+    fn expand_braces() {
+        assert_eq!(expand_alternates("vim"), None);
+        assert_eq!(
+            expand_alternates("{vim,emacs}-[0-9]*"),
+            Some(vec!["vim-[0-9]*".to_string(), "emacs-[0-9]*".to_string()])
+        );
+        assert_eq!(
+            expand_alternates("a{b,{c,d}}e"),
+            Some(vec!["abe".to_string(), "a{c,d}e".to_string()])
+        );
+    }
+
+    #[test]
+    fn pkg_match_patterns() {
+        assert!(pkg_match("vim-8.1.1004", "vim-8.1.1004"));
+        assert!(!pkg_match("vim", "vim-8.1.1004"));
+        assert!(pkg_match("vim-[0-9]*", "vim-8.1.1004"));
+        assert!(!pkg_match("vim-[0-9]*", "vim-share-8.1.1004"));
+        assert!(pkg_match("vim>=8.1.2000", "vim-8.1.2000nb1"));
+        assert!(!pkg_match("vim>=8.1.2000", "vim-8.1.1004"));
+        assert!(pkg_match("vim-share<9", "vim-share-8.1.1004"));
+        assert!(!pkg_match("vim<9", "vim-share-8.1.1004"));
+        assert!(!pkg_match("vim<9", "vim"));
+        assert!(pkg_match("{vim,nvi}-[0-9]*", "nvi-1.81.6nb7"));
+        assert!(pkg_match("{vim,nvi}>=1", "vim-8.1.1004"));
+        assert!(!pkg_match("{vim,nvi}>=2", "nvi-1.81.6nb7"));
+    }
+}
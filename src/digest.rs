@@ -0,0 +1,175 @@
+/*
+ * Copyright (c) 2019 Jonathan Perkin <jonathan@perkin.org.uk>
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ *
+ * digest.rs - check files against published SHA512 or SHA256 digests.
+ *
+ * Digest files may be in BSD format as written by "digest" and cksum -a
+ * ("SHA512 (pkg_summary.gz) = ..."), GNU format as written by sha512sum
+ * ("...  pkg_summary.gz"), or just contain a single bare digest.  Digests
+ * are handled as "ALGORITHM:hex" strings, which is also how they are
+ * recorded in the database.
+ */
+
+extern crate openssl;
+
+use openssl::hash::{hash, MessageDigest};
+
+/*
+ * Work out the algorithm from the length of a hex digest.
+ */
+fn algorithm(hex: &str) -> Option<&'static str> {
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    match hex.len() {
+        64 => Some("SHA256"),
+        128 => Some("SHA512"),
+        _ => None,
+    }
+}
+
+/*
+ * Find the digest for a file name in the contents of a digest file.
+ */
+pub fn find(contents: &str, filename: &str) -> Option<String> {
+    let lines: Vec<&str> = contents
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect();
+
+    for line in &lines {
+        /* BSD format */
+        if let Some(open) = line.find(" (") {
+            let tag = &line[..open];
+            let rest = &line[open + 2..];
+            if let Some(close) = rest.rfind(") = ") {
+                let hex = &rest[close + 4..];
+                if &rest[..close] == filename && algorithm(hex) == Some(tag) {
+                    return Some(format!("{}:{}", tag, hex.to_lowercase()));
+                }
+            }
+            continue;
+        }
+
+        /* GNU format, "*" marks binary mode */
+        let mut fields = line.split_whitespace();
+        if let (Some(hex), Some(name), None) =
+            (fields.next(), fields.next(), fields.next())
+        {
+            if name.trim_start_matches('*') == filename {
+                if let Some(alg) = algorithm(hex) {
+                    return Some(format!("{}:{}", alg, hex.to_lowercase()));
+                }
+            }
+        }
+    }
+
+    /* A single bare digest */
+    if let [hex] = lines.as_slice() {
+        if let Some(alg) = algorithm(hex) {
+            return Some(format!("{}:{}", alg, hex.to_lowercase()));
+        }
+    }
+    None
+}
+
+/*
+ * Return whether data matches a digest returned by find().
+ */
+pub fn matches(digest: &str, data: &[u8]) -> Result<bool, String> {
+    let (alg, hex) = match digest.find(':') {
+        Some(i) => (&digest[..i], &digest[i + 1..]),
+        None => return Err(format!("invalid digest \"{}\"", digest)),
+    };
+    let md = match alg {
+        "SHA256" => MessageDigest::sha256(),
+        "SHA512" => MessageDigest::sha512(),
+        _ => return Err(format!("unsupported digest algorithm {}", alg)),
+    };
+    let computed: String = hash(md, data)
+        .map_err(|e| e.to_string())?
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    Ok(computed == hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Digests of "abc". */
+    const SHA256: &str =
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    const SHA512: &str = "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea2\
+                          0a9eeee64b55d39a2192992a274fc1a836ba3c23a3feebbd\
+                          454d4423643ce80e2a9ac94fa54ca49f";
+
+    #[test]
+    fn bsd_format() {
+        let contents = format!(
+            "SHA512 (pkg_summary.bz2) = {}\n\
+             SHA512 (pkg_summary.gz) = {}\n",
+            "0".repeat(128),
+            SHA512.to_uppercase()
+        );
+        assert_eq!(
+            find(&contents, "pkg_summary.gz"),
+            Some(format!("SHA512:{}", SHA512))
+        );
+        assert_eq!(find(&contents, "pkg_summary.xz"), None);
+
+        /* The tag must agree with the length of the digest */
+        let contents = format!("SHA256 (pkg_summary.gz) = {}\n", SHA512);
+        assert_eq!(find(&contents, "pkg_summary.gz"), None);
+    }
+
+    #[test]
+    fn gnu_format() {
+        let contents = format!(
+            "{}  pkg_summary.bz2\n{} *pkg_summary.gz\n",
+            "0".repeat(64),
+            SHA256
+        );
+        assert_eq!(
+            find(&contents, "pkg_summary.gz"),
+            Some(format!("SHA256:{}", SHA256))
+        );
+        assert_eq!(find(&contents, "pkg_summary"), None);
+    }
+
+    #[test]
+    fn bare_digest() {
+        assert_eq!(
+            find(&format!("{}\n", SHA256), "pkg_summary.gz"),
+            Some(format!("SHA256:{}", SHA256))
+        );
+        assert_eq!(find(&SHA256[1..], "pkg_summary.gz"), None);
+        assert_eq!(find(&format!("{}\n{}\n", SHA256, SHA512), "x"), None);
+    }
+
+    #[test]
+    fn matching() {
+        let sha256 = format!("SHA256:{}", SHA256);
+        let sha512 = format!("SHA512:{}", SHA512);
+        assert_eq!(matches(&sha256, b"abc"), Ok(true));
+        assert_eq!(matches(&sha512, b"abc"), Ok(true));
+        assert_eq!(matches(&sha256, b"abd"), Ok(false));
+        assert_eq!(matches(&sha512, b"ab"), Ok(false));
+        assert!(matches(SHA256, b"abc").is_err());
+        assert!(matches(&format!("MD5:{}", SHA256), b"abc").is_err());
+    }
+}
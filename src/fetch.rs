@@ -0,0 +1,371 @@
+/*
+ * Copyright (c) 2019 Jonathan Perkin <jonathan@perkin.org.uk>
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ *
+ * fetch.rs - HTTP transfers from remote repositories.
+ */
+
+extern crate openssl;
+extern crate reqwest;
+
+use crate::auth::{self, Credentials};
+use crate::config;
+use crate::progress::Progress;
+use openssl::pkcs12::Pkcs12;
+use openssl::pkey::PKey;
+use openssl::stack::Stack;
+use openssl::x509::X509;
+use std::fs;
+use std::io::Read;
+use std::time::{Duration, SystemTime};
+
+/*
+ * The longest to wait between attempts, however many retries are made.
+ */
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(300);
+
+/*
+ * A completed transfer.  The body is read in full so that a transfer which
+ * fails part way through can be retried in the same way as one which fails
+ * to connect.
+ */
+#[derive(Debug)]
+pub struct Response {
+    status: reqwest::StatusCode,
+    last_modified: Option<i64>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    pub fn status(&self) -> reqwest::StatusCode {
+        self.status
+    }
+
+    /*
+     * Last-Modified header as seconds since the epoch, if the server sent
+     * one that we could parse.
+     */
+    pub fn last_modified(&self) -> Option<i64> {
+        self.last_modified
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+}
+
+/*
+ * Return whether a host matches any entry in a no_proxy list.  Entries match
+ * the host itself or any subdomain of it, and "*" matches everything.
+ */
+fn no_proxy_match(host: &str, no_proxy: &[String]) -> bool {
+    no_proxy.iter().any(|np| {
+        let np = np.trim_start_matches('.');
+        np == "*"
+            || host.eq_ignore_ascii_case(np)
+            || host
+                .to_lowercase()
+                .ends_with(&format!(".{}", np.to_lowercase()))
+    })
+}
+
+/*
+ * Look up the proxy to use for a URL scheme from the environment, using the
+ * same variables as curl and most other tools.
+ */
+fn env_proxy(scheme: &str) -> Option<String> {
+    let mut vars = vec![format!("{}_proxy", scheme)];
+
+    /*
+     * HTTP_PROXY is deliberately ignored as it can be set by a client in
+     * CGI environments.
+     */
+    if scheme != "http" {
+        vars.push(format!("{}_PROXY", scheme.to_uppercase()));
+    }
+    vars.push("all_proxy".to_string());
+    vars.push("ALL_PROXY".to_string());
+    vars.iter()
+        .filter_map(|v| std::env::var(v).ok())
+        .find(|v| !v.is_empty())
+}
+
+fn proxy(cfg: &config::Config) -> reqwest::Proxy {
+    let proxy = cfg.proxy().clone();
+    let no_proxy = cfg.no_proxy().clone();
+    reqwest::Proxy::custom(move |url| {
+        if let Some(host) = url.host_str() {
+            if no_proxy_match(host, &no_proxy) {
+                return None;
+            }
+        }
+        let p = proxy.clone().or_else(|| env_proxy(url.scheme()))?;
+
+        /* Allow "host:port" as well as a full URL. */
+        if p.contains("://") {
+            reqwest::Url::parse(&p).ok()
+        } else {
+            reqwest::Url::parse(&format!("http://{}", p)).ok()
+        }
+    })
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    fs::read(path).map_err(|e| format!("{}: {}", path, e).into())
+}
+
+/*
+ * Load every certificate from a PEM bundle.  reqwest only parses the first
+ * certificate it finds, so split them up first.
+ */
+fn ca_certificates(
+    path: &str,
+) -> Result<Vec<reqwest::Certificate>, Box<dyn std::error::Error>> {
+    let mut certs = vec![];
+    for cert in X509::stack_from_pem(&read_file(path)?)? {
+        certs.push(reqwest::Certificate::from_der(&cert.to_der()?)?);
+    }
+    if certs.is_empty() {
+        return Err(format!("{}: no certificates found", path).into());
+    }
+    Ok(certs)
+}
+
+/*
+ * Build a client identity for mutual TLS from a PEM certificate and key.
+ * The native TLS backend only accepts PKCS#12, so convert to that first.
+ * If no key is given it is expected to be in the certificate file.
+ */
+fn client_identity(
+    cert: &str,
+    key: Option<&str>,
+) -> Result<reqwest::Identity, Box<dyn std::error::Error>> {
+    let certpem = read_file(cert)?;
+    let keypem = match key {
+        Some(k) => read_file(k)?,
+        None => certpem.clone(),
+    };
+    let mut chain = X509::stack_from_pem(&certpem)?.into_iter();
+    let leaf = chain
+        .next()
+        .ok_or_else(|| format!("{}: no certificates found", cert))?;
+    let pkey = PKey::private_key_from_pem(&keypem)
+        .map_err(|e| format!("{}: {}", key.unwrap_or(cert), e))?;
+    let mut ca = Stack::new()?;
+    for c in chain {
+        ca.push(c)?;
+    }
+    let pkcs12 = Pkcs12::builder()
+        .name("pm")
+        .pkey(&pkey)
+        .cert(&leaf)
+        .ca(ca)
+        .build2("")?;
+    Ok(reqwest::Identity::from_pkcs12_der(&pkcs12.to_der()?, "")?)
+}
+
+/*
+ * Return a client configured with the transfer, proxy, and TLS settings for
+ * a repository.  Every download from a repository should use this.
+ */
+pub fn client(
+    cfg: &config::Config,
+    repo: &config::Repository,
+) -> Result<reqwest::Client, Box<dyn std::error::Error>> {
+    let mut builder = reqwest::Client::builder()
+        .connect_timeout(repo.connect_timeout())
+        .timeout(repo.read_timeout())
+        .proxy(proxy(cfg));
+    if let Some(bundle) = repo.ca_bundle() {
+        for cert in ca_certificates(bundle)? {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+    if let Some(cert) = repo.client_cert() {
+        builder = builder.identity(client_identity(
+            cert,
+            repo.client_key().as_ref().map(String::as_str),
+        )?);
+    }
+    Ok(builder.build()?)
+}
+
+fn send_once(
+    client: &reqwest::Client,
+    creds: &Option<Credentials>,
+    method: &reqwest::Method,
+    url: &str,
+    since: Option<i64>,
+    progress: Option<&mut Progress>,
+) -> Result<Response, Box<dyn std::error::Error>> {
+    let mut req = client.request(method.clone(), url);
+    match creds {
+        Some(Credentials::Basic { username, password }) => {
+            req = req.basic_auth(username, password.as_ref());
+        }
+        Some(Credentials::Bearer(token)) => {
+            req = req.bearer_auth(token);
+        }
+        None => {}
+    }
+    if let Some(since) = since {
+        let since = SystemTime::UNIX_EPOCH
+            + std::time::Duration::from_secs(since.max(0) as u64);
+        req = req.header(
+            reqwest::header::IF_MODIFIED_SINCE,
+            httpdate::fmt_http_date(since),
+        );
+    }
+    let mut res = req.send()?;
+    let status = res.status();
+    let last_modified = res
+        .headers()
+        .get(reqwest::header::LAST_MODIFIED)
+        .and_then(|lm| lm.to_str().ok())
+        .and_then(|lm| httpdate::parse_http_date(lm).ok())
+        .and_then(|lm| lm.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map(|lm| lm.as_secs() as i64);
+    let mut body = vec![];
+    if status.is_success() && method != reqwest::Method::HEAD {
+        match progress {
+            Some(progress) => {
+                let total = res.content_length();
+                let mut buf = [0; 65536];
+                loop {
+                    let n = res.read(&mut buf)?;
+                    if n == 0 {
+                        break;
+                    }
+                    body.extend_from_slice(&buf[..n]);
+                    progress.download(body.len() as u64, total);
+                }
+                progress.download_done(body.len() as u64);
+            }
+            None => {
+                res.read_to_end(&mut body)?;
+            }
+        }
+    }
+    if status.is_server_error() {
+        return Err(format!("{} returned {}", url, status).into());
+    }
+    Ok(Response {
+        status,
+        last_modified,
+        body,
+    })
+}
+
+/*
+ * Send a request, retrying on connection failures, timeouts, and server
+ * errors using the repository's retry settings.  The delay between attempts
+ * doubles each time, up to MAX_RETRY_BACKOFF.  Client errors such as 404 are returned immediately as
+ * they are not going to improve with a retry.
+ */
+fn send(
+    client: &reqwest::Client,
+    repo: &config::Repository,
+    method: reqwest::Method,
+    url: &str,
+    since: Option<i64>,
+    mut progress: Option<&mut Progress>,
+) -> Result<Response, Box<dyn std::error::Error>> {
+    let creds = auth::credentials(repo, url)?;
+    let mut delay = repo.retry_backoff().min(MAX_RETRY_BACKOFF);
+    let mut attempt = 0;
+    loop {
+        match send_once(
+            client,
+            &creds,
+            &method,
+            url,
+            since,
+            progress.as_deref_mut(),
+        ) {
+            Ok(res) => return Ok(res),
+            Err(e) => {
+                if attempt >= repo.retries() {
+                    return Err(e);
+                }
+                attempt += 1;
+                eprintln!(
+                    "WARNING: {}, retrying in {}s ({}/{})",
+                    e,
+                    delay.as_secs(),
+                    attempt,
+                    repo.retries()
+                );
+                std::thread::sleep(delay);
+                delay = delay.saturating_mul(2).min(MAX_RETRY_BACKOFF);
+            }
+        }
+    }
+}
+
+/*
+ * Fetch a URL.
+ */
+pub fn get(
+    client: &reqwest::Client,
+    repo: &config::Repository,
+    url: &str,
+) -> Result<Response, Box<dyn std::error::Error>> {
+    send(client, repo, reqwest::Method::GET, url, None, None)
+}
+
+/*
+ * Fetch a URL, reporting progress as the body is downloaded.  If since is
+ * set the body is only sent if it has been modified since then, as with
+ * get_if_modified().
+ */
+pub fn get_with_progress(
+    client: &reqwest::Client,
+    repo: &config::Repository,
+    url: &str,
+    since: Option<i64>,
+    progress: &mut Progress,
+) -> Result<Response, Box<dyn std::error::Error>> {
+    send(
+        client,
+        repo,
+        reqwest::Method::GET,
+        url,
+        since,
+        Some(progress),
+    )
+}
+
+/*
+ * Fetch only the headers for a URL, leaving the body empty.
+ */
+pub fn head(
+    client: &reqwest::Client,
+    repo: &config::Repository,
+    url: &str,
+) -> Result<Response, Box<dyn std::error::Error>> {
+    send(client, repo, reqwest::Method::HEAD, url, None, None)
+}
+
+/*
+ * Fetch a URL only if it has been modified since a time in seconds since the
+ * epoch, otherwise the server responds with 304 Not Modified and no body.
+ */
+pub fn get_if_modified(
+    client: &reqwest::Client,
+    repo: &config::Repository,
+    url: &str,
+    since: i64,
+) -> Result<Response, Box<dyn std::error::Error>> {
+    send(client, repo, reqwest::Method::GET, url, Some(since), None)
+}
@@ -0,0 +1,93 @@
+/*
+ * Copyright (c) 2019 Jonathan Perkin <jonathan@perkin.org.uk>
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ *
+ * info.rs - handle "pm info" command.
+ */
+
+use crate::audit::Vulnerabilities;
+use crate::config;
+use crate::list;
+use crate::output;
+use crate::pmdb::PMDB;
+use crate::select;
+
+/*
+ * Show everything we know about the available packages matching a PKGBASE
+ * or full PKGNAME.
+ */
+pub fn run(
+    cfg: &config::Config,
+    db: &mut PMDB,
+    pkgname: &str,
+    no_vulnerable: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let vulns = Vulnerabilities::load(db)?;
+    let pkgs: Vec<i64> = select::remote_pkglist_by_name(cfg, db, pkgname)?
+        .iter()
+        .filter(|p| p.pkgbase() == pkgname || p.pkgname() == pkgname)
+        .filter(|p| !no_vulnerable || !vulns.is_vulnerable(p.pkgname()))
+        .map(|p| *p.id())
+        .collect();
+    if pkgs.is_empty() {
+        eprintln!(
+            "No package matching {} available for prefix={}",
+            pkgname,
+            cfg.prefix()
+        );
+        std::process::exit(1);
+    }
+    if output::wanted(cfg) {
+        let pkgs = pkgs
+            .iter()
+            .map(|id| db.get_remote_pkg(*id))
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        return list::print_remote(cfg, db, &pkgs, &vulns);
+    }
+    for (i, id) in pkgs.iter().enumerate() {
+        let pkg = db.get_remote_pkg(*id)?;
+        if i > 0 {
+            println!();
+        }
+        let fields = [
+            ("PKGNAME", pkg.pkgname().to_string()),
+            ("REPOSITORY", pkg.repository().to_string()),
+            ("COMMENT", pkg.comment().to_string()),
+            ("CATEGORIES", pkg.categories().to_string()),
+            ("PKGPATH", pkg.pkgpath().to_string()),
+            ("HOMEPAGE", pkg.homepage().to_string()),
+            ("LICENSE", pkg.license().to_string()),
+            ("BUILD_DATE", pkg.build_date().to_string()),
+            ("OPSYS", pkg.opsys().to_string()),
+            ("OS_VERSION", pkg.os_version().to_string()),
+            ("PKG_OPTIONS", pkg.pkg_options().to_string()),
+            ("FILE_NAME", pkg.file_name().to_string()),
+            ("FILE_SIZE", pkg.file_size().to_string()),
+            ("SIZE_PKG", pkg.size_pkg().to_string()),
+        ];
+
+        /* Optional fields are left out if the package doesn't set them. */
+        for (key, val) in fields.iter().filter(|(_, val)| !val.is_empty()) {
+            println!("{:16} {}", key, val);
+        }
+        for (_, vtype, url) in vulns.matching(pkg.pkgname()) {
+            println!("{:16} {} {}", "VULNERABILITY", vtype, url);
+        }
+        println!("DESCRIPTION");
+        for line in pkg.description().lines() {
+            println!("    {}", line);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,362 @@
+/*
+ * Copyright (c) 2019 Jonathan Perkin <jonathan@perkin.org.uk>
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ *
+ * license.rs - check package licenses against ACCEPTABLE_LICENSES, handle
+ * "pm licenses" command.
+ *
+ * LICENSE is either a single license name or an expression combining them
+ * with AND and OR, for example "gnu-gpl-v2 OR mit".  As with pkg_install,
+ * AND and OR may not be mixed without parentheses to make the meaning
+ * clear.
+ */
+
+use crate::config;
+use crate::list::PackageList;
+use crate::pmdb::PMDB;
+use std::collections::HashSet;
+use std::fs;
+
+/*
+ * Evaluate a license expression as it is parsed.
+ */
+struct Parser<'a> {
+    tokens: Vec<&'a str>,
+    pos: usize,
+    accept: &'a dyn Fn(&str) -> bool,
+}
+
+fn tokenize(expr: &str) -> Vec<&str> {
+    let mut tokens = vec![];
+    for word in expr.split_whitespace() {
+        let mut start = 0;
+        for (i, c) in word.char_indices() {
+            if c == '(' || c == ')' {
+                if start < i {
+                    tokens.push(&word[start..i]);
+                }
+                tokens.push(&word[i..i + 1]);
+                start = i + 1;
+            }
+        }
+        if start < word.len() {
+            tokens.push(&word[start..]);
+        }
+    }
+    tokens
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Option<&'a str> {
+        let token = self.tokens.get(self.pos).copied();
+        self.pos += 1;
+        token
+    }
+
+    fn term(&mut self) -> Result<bool, String> {
+        match self.next() {
+            Some("(") => {
+                let result = self.expr()?;
+                match self.next() {
+                    Some(")") => Ok(result),
+                    _ => Err("missing \")\"".to_string()),
+                }
+            }
+            Some(t @ (")" | "AND" | "OR")) => {
+                Err(format!("unexpected \"{}\"", t))
+            }
+            Some(name) => {
+                if name.chars().all(|c| {
+                    c.is_ascii_lowercase()
+                        || c.is_ascii_digit()
+                        || "-._+".contains(c)
+                }) {
+                    Ok((self.accept)(name))
+                } else {
+                    Err(format!("invalid license name \"{}\"", name))
+                }
+            }
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+
+    fn expr(&mut self) -> Result<bool, String> {
+        let mut result = self.term()?;
+        let mut op = None;
+        while let Some(&t) = self.tokens.get(self.pos) {
+            if t != "AND" && t != "OR" {
+                break;
+            }
+            if op.is_some() && op != Some(t) {
+                return Err("AND and OR mixed without parentheses".to_string());
+            }
+            op = Some(t);
+            self.pos += 1;
+
+            /* Every term is parsed so that errors are always found */
+            let rhs = self.term()?;
+            result = if t == "AND" {
+                result && rhs
+            } else {
+                result || rhs
+            };
+        }
+        Ok(result)
+    }
+}
+
+/*
+ * Return whether a license expression is satisfied by the licenses that
+ * accept() returns true for.
+ */
+pub fn evaluate(
+    expr: &str,
+    accept: &dyn Fn(&str) -> bool,
+) -> Result<bool, String> {
+    let mut parser = Parser {
+        tokens: tokenize(expr),
+        pos: 0,
+        accept,
+    };
+    let result = parser.expr()?;
+    match parser.next() {
+        Some(t) => Err(format!("unexpected \"{}\"", t)),
+        None => Ok(result),
+    }
+}
+
+/*
+ * Read ACCEPTABLE_LICENSES from a mk.conf.  Only plain assignments and
+ * appends are understood, and variable references are ignored.
+ */
+fn read_mk_conf(path: &str) -> Result<Vec<String>, String> {
+    let contents =
+        fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let contents = contents.replace("\\\n", " ");
+    let mut licenses = vec![];
+    for line in contents.lines() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        let rest = match line.strip_prefix("ACCEPTABLE_LICENSES") {
+            Some(rest) => rest.trim_start(),
+            None => continue,
+        };
+        let value = if let Some(value) = rest.strip_prefix("+=") {
+            value
+        } else if let Some(value) = rest.strip_prefix('=') {
+            licenses.clear();
+            value
+        } else {
+            continue;
+        };
+        licenses.extend(
+            value
+                .split_whitespace()
+                .filter(|l| !l.starts_with('$'))
+                .map(str::to_string),
+        );
+    }
+    Ok(licenses)
+}
+
+/*
+ * The licenses accepted for a prefix.
+ */
+pub struct Policy {
+    licenses: HashSet<String>,
+}
+
+impl Policy {
+    /*
+     * Return the policy for a prefix, combining any licenses listed in
+     * pm.toml with those from mk.conf or pkg_install.conf.  None if no
+     * policy has been configured.
+     */
+    pub fn for_prefix(
+        prefix: &config::Prefix,
+    ) -> Result<Option<Policy>, String> {
+        let mut licenses: Option<HashSet<String>> = prefix
+            .acceptable_licenses()
+            .as_ref()
+            .map(|l| l.iter().cloned().collect());
+        let from = match prefix.acceptable_licenses_from() {
+            Some(from) if from == "pkg_install.conf" => prefix
+                .pkg_install_licenses()
+                .map(|l| l.split_whitespace().map(str::to_string).collect()),
+            Some(from) if from == "mk.conf" => {
+                Some(read_mk_conf(&format!("{}/etc/mk.conf", prefix.path()))?)
+            }
+            Some(from) => Some(read_mk_conf(from)?),
+            None => None,
+        };
+        if let Some(from) = from {
+            licenses.get_or_insert_with(HashSet::new).extend(from);
+        }
+        Ok(licenses.map(|licenses| Policy { licenses }))
+    }
+
+    /*
+     * Return whether a package's LICENSE is acceptable.  Packages without a
+     * LICENSE are accepted, as with pkg_install.
+     */
+    pub fn acceptable(&self, license: &str) -> Result<bool, String> {
+        if license.trim().is_empty() {
+            return Ok(true);
+        }
+        evaluate(license, &|l| self.licenses.contains(l))
+    }
+
+    /*
+     * Whether a package should be shown when hiding unacceptable ones.
+     */
+    pub fn allows(&self, pkg: &PackageList) -> bool {
+        self.acceptable(pkg.license()).unwrap_or(false)
+    }
+}
+
+/*
+ * Return the policy for the default prefix, which must have one.
+ */
+pub fn default_policy(cfg: &config::Config) -> Policy {
+    let prefix = match cfg.get_prefix(cfg.prefix()) {
+        Some(prefix) => prefix,
+        None => {
+            eprintln!("ERROR: Prefix {} is not configured", cfg.prefix());
+            std::process::exit(1);
+        }
+    };
+    match Policy::for_prefix(prefix) {
+        Ok(Some(policy)) => policy,
+        Ok(None) => {
+            eprintln!(
+                "ERROR: No acceptable_licenses configured for prefix={}",
+                cfg.prefix()
+            );
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/*
+ * Exit status used when installed packages have licenses that are not
+ * acceptable, and for any error, such as no license policy being configured.
+ */
+const EXIT_VIOLATIONS: i32 = 1;
+pub const EXIT_FAILED: i32 = 2;
+
+/*
+ * Report installed packages whose licenses are not acceptable, in every
+ * prefix that has a license policy.
+ */
+pub fn run(
+    cfg: &config::Config,
+    db: &mut PMDB,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut checked = 0;
+    let mut violations = 0;
+    for prefix in cfg.prefixes() {
+        let policy = match Policy::for_prefix(prefix) {
+            Ok(Some(policy)) => policy,
+            Ok(None) => continue,
+            Err(e) => {
+                eprintln!("ERROR: {}", e);
+                std::process::exit(EXIT_FAILED);
+            }
+        };
+        checked += 1;
+        for pkg in db.get_local_pkglist_by_prefix(prefix.path())? {
+            match policy.acceptable(pkg.license()) {
+                Ok(true) => continue,
+                Ok(false) => println!(
+                    "Package {} in {} has an unacceptable license: {}",
+                    pkg.pkgname(),
+                    prefix.path(),
+                    pkg.license()
+                ),
+                Err(e) => println!(
+                    "Package {} in {} has an invalid license \"{}\": {}",
+                    pkg.pkgname(),
+                    prefix.path(),
+                    pkg.license(),
+                    e
+                ),
+            }
+            violations += 1;
+        }
+    }
+    if checked == 0 {
+        eprintln!("ERROR: No prefix has acceptable_licenses configured");
+        std::process::exit(EXIT_FAILED);
+    }
+    if violations > 0 {
+        std::process::exit(EXIT_VIOLATIONS);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accepts(expr: &str) -> Result<bool, String> {
+        evaluate(expr, &|l| l == "mit" || l == "modified-bsd")
+    }
+
+    #[test]
+    fn single_license() {
+        assert_eq!(accepts("mit"), Ok(true));
+        assert_eq!(accepts("gnu-gpl-v2"), Ok(false));
+        assert_eq!(accepts("(mit)"), Ok(true));
+    }
+
+    #[test]
+    fn and_or() {
+        assert_eq!(accepts("gnu-gpl-v2 OR mit"), Ok(true));
+        assert_eq!(accepts("gnu-gpl-v2 OR gnu-gpl-v3"), Ok(false));
+        assert_eq!(accepts("mit AND modified-bsd"), Ok(true));
+        assert_eq!(accepts("mit AND gnu-gpl-v2"), Ok(false));
+        assert_eq!(accepts("mit AND modified-bsd AND gnu-gpl-v2"), Ok(false));
+        assert_eq!(accepts("gnu-gpl-v2 OR gnu-gpl-v3 OR mit"), Ok(true));
+    }
+
+    #[test]
+    fn parentheses() {
+        assert_eq!(accepts("(gnu-gpl-v2 OR mit) AND modified-bsd"), Ok(true));
+        assert_eq!(accepts("gnu-gpl-v2 AND (mit OR modified-bsd)"), Ok(false));
+        assert_eq!(accepts("(gnu-gpl-v2 AND mit) OR modified-bsd"), Ok(true));
+        assert_eq!(accepts("((mit))"), Ok(true));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(accepts("mit AND modified-bsd OR gnu-gpl-v2").is_err());
+        assert!(accepts("mit OR").is_err());
+        assert!(accepts("AND mit").is_err());
+        assert!(accepts("(mit").is_err());
+        assert!(accepts("mit)").is_err());
+        assert!(accepts("mit modified-bsd").is_err());
+        assert!(accepts("MIT").is_err());
+        assert!(accepts("").is_err());
+    }
+
+    #[test]
+    fn errors_after_match() {
+        /* Every term is checked even once the result is known */
+        assert!(accepts("mit OR (gnu-gpl-v2").is_err());
+        assert!(accepts("gnu-gpl-v2 AND Bad").is_err());
+    }
+}
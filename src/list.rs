@@ -16,8 +16,13 @@
  * list.rs - handle commands that require a list of packages.
  */
 
+use crate::audit::Vulnerabilities;
 use crate::config;
+use crate::license;
+use crate::output::{self, Record};
 use crate::pmdb::PMDB;
+use crate::select;
+use std::collections::HashSet;
 
 /*
  * A PackageList is an entry from the database of either a local or remote
@@ -28,10 +33,12 @@ use crate::pmdb::PMDB;
  * derive from Default and ensure all entries are set to default values and
  * it is up to callers to use the correct information.
  */
+#[allow(dead_code)]
 #[derive(Clone, Debug, Default)]
 pub struct PackageList {
     pub id: i64,
     pub repository_id: i64,
+    pub repository: String,
     pub automatic: bool,
     pub build_date: String,
     pub categories: String,
@@ -52,14 +59,17 @@ pub struct PackageList {
     pub size_pkg: i64,
 }
 
+#[allow(dead_code)]
 impl PackageList {
-    #[allow(dead_code)]
     pub fn id(&self) -> &i64 {
         &self.id
     }
     pub fn repository_id(&self) -> &i64 {
         &self.repository_id
     }
+    pub fn repository(&self) -> &String {
+        &self.repository
+    }
     pub fn automatic(&self) -> &bool {
         &self.automatic
     }
@@ -119,14 +129,96 @@ impl PackageList {
 pub fn avail(
     cfg: &config::Config,
     db: &mut PMDB,
-) -> Result<(), Box<std::error::Error>> {
-    let pkgs = db.get_remote_pkglist_by_prefix(cfg.prefix())?;
+    no_vulnerable: bool,
+    acceptable: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let pkgs = select::remote_pkglist(cfg, db, output::wanted(cfg))?;
     if pkgs.is_empty() {
         eprintln!("No packages available for prefix={}", cfg.prefix());
         std::process::exit(1);
     }
+    let vulns = Vulnerabilities::load(db)?;
+    let pkgs = filter_vulnerable(pkgs, &vulns, no_vulnerable);
+    let pkgs = filter_licenses(cfg, pkgs, acceptable);
+    print_remote(cfg, db, &pkgs, &vulns)
+}
+
+/*
+ * Remove packages with known vulnerabilities if requested.
+ */
+pub fn filter_vulnerable(
+    pkgs: Vec<PackageList>,
+    vulns: &Vulnerabilities,
+    no_vulnerable: bool,
+) -> Vec<PackageList> {
+    if !no_vulnerable {
+        return pkgs;
+    }
+    pkgs.into_iter()
+        .filter(|pkg| !vulns.is_vulnerable(pkg.pkgname()))
+        .collect()
+}
+
+/*
+ * Remove packages whose licenses are not acceptable for the default prefix
+ * if requested.
+ */
+pub fn filter_licenses(
+    cfg: &config::Config,
+    pkgs: Vec<PackageList>,
+    acceptable: bool,
+) -> Vec<PackageList> {
+    if !acceptable {
+        return pkgs;
+    }
+    let policy = license::default_policy(cfg);
+    pkgs.into_iter().filter(|pkg| policy.allows(pkg)).collect()
+}
+
+/*
+ * Print available packages, by default along with the repository each one
+ * comes from and marking any with known vulnerabilities.
+ */
+pub fn print_remote(
+    cfg: &config::Config,
+    db: &mut PMDB,
+    pkgs: &[PackageList],
+    vulns: &Vulnerabilities,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if output::wanted(cfg) {
+        let installed: HashSet<String> = db
+            .get_local_pkglist_by_prefix(cfg.prefix())?
+            .into_iter()
+            .map(|pkg| pkg.pkgname)
+            .collect();
+        let records: Vec<Record> = pkgs
+            .iter()
+            .map(|pkg| {
+                Record::remote(
+                    pkg,
+                    cfg.prefix(),
+                    installed.contains(pkg.pkgname()),
+                    vulns.is_vulnerable(pkg.pkgname()),
+                )
+            })
+            .collect();
+        return output::print(cfg, &records);
+    }
+    let width = pkgs.iter().map(|p| p.repository().len()).max().unwrap_or(0);
     for pkg in pkgs {
-        println!("{:20} {}", pkg.pkgname(), pkg.comment());
+        let mark = if vulns.is_vulnerable(pkg.pkgname()) {
+            " (vulnerable)"
+        } else {
+            ""
+        };
+        println!(
+            "{:20} {:width$} {}{}",
+            pkg.pkgname(),
+            pkg.repository(),
+            pkg.comment(),
+            mark,
+            width = width
+        );
     }
     Ok(())
 }
@@ -134,12 +226,32 @@ pub fn avail(
 pub fn list(
     cfg: &config::Config,
     db: &mut PMDB,
-) -> Result<(), Box<std::error::Error>> {
+) -> Result<(), Box<dyn std::error::Error>> {
     let pkgs = db.get_local_pkglist_by_prefix(cfg.prefix())?;
     if pkgs.is_empty() {
         eprintln!("No packages recorded under {}", cfg.prefix());
         std::process::exit(1);
     }
+    if output::wanted(cfg) {
+        let available: HashSet<String> =
+            select::remote_pkglist(cfg, db, false)?
+                .into_iter()
+                .map(|pkg| pkg.pkgname)
+                .collect();
+        let vulns = Vulnerabilities::load(db)?;
+        let records: Vec<Record> = pkgs
+            .iter()
+            .map(|pkg| {
+                Record::local(
+                    pkg,
+                    cfg.prefix(),
+                    available.contains(pkg.pkgname()),
+                    vulns.is_vulnerable(pkg.pkgname()),
+                )
+            })
+            .collect();
+        return output::print(cfg, &records);
+    }
     for pkg in pkgs {
         println!("{:20} {}", pkg.pkgname(), pkg.comment());
     }
@@ -16,11 +16,27 @@
  * pm(1) - a package manager for pkgsrc.
  */
 
+mod audit;
+mod auth;
+mod changes;
 mod config;
+mod db;
+mod dewey;
+mod digest;
+mod fetch;
+mod info;
+mod license;
 mod list;
+mod output;
 mod pmdb;
+mod progress;
+mod repo;
 mod search;
+mod select;
+mod signature;
+mod summary;
 mod update;
+mod verify;
 
 extern crate bzip2;
 extern crate chrono;
@@ -28,10 +44,12 @@ extern crate flate2;
 extern crate httpdate;
 extern crate regex;
 extern crate rusqlite;
+extern crate serde_json;
 extern crate structopt;
 extern crate xz2;
 
 use crate::pmdb::PMDB;
+use std::path::PathBuf;
 use structopt::StructOpt;
 
 /*
@@ -48,6 +66,27 @@ pub struct OptArgs {
     config: Option<String>,
     #[structopt(short = "p", long = "prefix", help = "Set default prefix")]
     prefix: Option<String>,
+    #[structopt(
+        short = "r",
+        long = "repo",
+        help = "Only query the named repository"
+    )]
+    repo: Option<String>,
+    #[structopt(
+        short = "o",
+        long = "output",
+        help = "Output format for package lists",
+        default_value = "plain",
+        raw(possible_values = "output::FORMATS")
+    )]
+    output: output::Format,
+    #[structopt(
+        short = "f",
+        long = "format",
+        help = "Print package lists using a template such as '%-20N %c'",
+        conflicts_with = "output"
+    )]
+    format: Option<output::Template>,
     #[structopt(short = "v", long = "verbose", help = "Enable verbose output")]
     verbose: bool,
     #[structopt(subcommand)]
@@ -61,13 +100,65 @@ enum SubCmd {
         alias = "av",
         about = "List available packages"
     )]
-    Avail,
+    Avail {
+        #[structopt(
+            long = "no-vulnerable",
+            help = "Hide packages with known vulnerabilities"
+        )]
+        no_vulnerable: bool,
+        #[structopt(
+            long = "acceptable",
+            help = "Hide packages whose licenses are not acceptable"
+        )]
+        acceptable: bool,
+    },
+    #[structopt(
+        name = "audit",
+        about = "Report installed packages with known vulnerabilities"
+    )]
+    Audit,
+    #[structopt(name = "db", about = "Manage the pm database")]
+    Db {
+        #[structopt(subcommand)]
+        cmd: DbCmd,
+    },
+    #[structopt(name = "info", about = "Show details of available packages")]
+    Info {
+        #[structopt(help = "Package name (PKGBASE or PKGNAME)")]
+        pkgname: String,
+        #[structopt(
+            long = "no-vulnerable",
+            help = "Hide packages with known vulnerabilities"
+        )]
+        no_vulnerable: bool,
+    },
+    #[structopt(
+        name = "licenses",
+        about = "Report installed packages with unacceptable licenses"
+    )]
+    Licenses,
+    #[structopt(
+        name = "lint-summary",
+        about = "Check a pkg_summary file for problems"
+    )]
+    LintSummary {
+        #[structopt(
+            help = "pkg_summary file, optionally compressed",
+            parse(from_os_str)
+        )]
+        file: PathBuf,
+    },
     #[structopt(
         name = "list",
         alias = "ls",
         about = "List installed packages"
     )]
     List,
+    #[structopt(name = "repo", about = "Manage repositories")]
+    Repo {
+        #[structopt(subcommand)]
+        cmd: RepoCmd,
+    },
     #[structopt(
         name = "search",
         alias = "se",
@@ -75,37 +166,350 @@ enum SubCmd {
     )]
     Search {
         #[structopt(help = "Query string (regular expression)")]
-        query: String,
+        query: Option<String>,
+        #[structopt(
+            long = "text",
+            help = "Search descriptions for words, best match first"
+        )]
+        text: bool,
+        #[structopt(long = "comment", help = "Match COMMENT")]
+        comment: Option<String>,
+        #[structopt(long = "description", help = "Match DESCRIPTION")]
+        description: Option<String>,
+        #[structopt(long = "category", help = "Match any of CATEGORIES")]
+        category: Option<String>,
+        #[structopt(long = "pkgpath", help = "Match PKGPATH")]
+        pkgpath: Option<String>,
+        #[structopt(long = "homepage", help = "Match HOMEPAGE")]
+        homepage: Option<String>,
+        #[structopt(long = "license", help = "Match LICENSE")]
+        license: Option<String>,
+        #[structopt(long = "all", help = "Match any field, including PKGNAME")]
+        all: Option<String>,
+        #[structopt(
+            long = "exact",
+            help = "Match whole fields, or PKGBASE or PKGNAME for the query",
+            conflicts_with = "glob",
+            conflicts_with = "fuzzy"
+        )]
+        exact: bool,
+        #[structopt(
+            long = "glob",
+            help = "Match pkgsrc patterns such as 'vim-[0-9]*' or 'vim>=9'",
+            conflicts_with = "fuzzy"
+        )]
+        glob: bool,
+        #[structopt(
+            long = "fuzzy",
+            help = "Match names with typos, closest first"
+        )]
+        fuzzy: bool,
+        #[structopt(long = "case-sensitive", help = "Match case exactly")]
+        case_sensitive: bool,
+        #[structopt(
+            long = "no-vulnerable",
+            help = "Hide packages with known vulnerabilities"
+        )]
+        no_vulnerable: bool,
+        #[structopt(
+            long = "acceptable",
+            help = "Hide packages whose licenses are not acceptable"
+        )]
+        acceptable: bool,
     },
     #[structopt(
         name = "update",
         alias = "up",
         about = "Update pkg_summary from each configured repository"
     )]
-    Update,
+    Update {
+        #[structopt(
+            long = "check",
+            help = "Report pending updates without changing anything",
+            conflicts_with = "changes_json",
+            conflicts_with = "save_changes",
+            conflicts_with = "force"
+        )]
+        check: bool,
+        #[structopt(
+            short = "f",
+            long = "force",
+            help = "Refresh even if already up to date"
+        )]
+        force: bool,
+        #[structopt(
+            long = "changes-json",
+            help = "Print changed packages as JSON"
+        )]
+        changes_json: bool,
+        #[structopt(
+            long = "save-changes",
+            help = "Save changed packages as JSON to a file",
+            parse(from_os_str)
+        )]
+        save_changes: Option<PathBuf>,
+        #[structopt(help = "Only update these prefixes or repositories")]
+        targets: Vec<String>,
+    },
+    #[structopt(
+        name = "verify-pkg",
+        about = "Verify the signature of a binary package"
+    )]
+    VerifyPkg {
+        #[structopt(help = "Binary package file", parse(from_os_str))]
+        file: PathBuf,
+    },
 }
 
-fn main() -> Result<(), Box<std::error::Error>> {
+#[derive(Debug, StructOpt)]
+enum DbCmd {
+    #[structopt(name = "migrate", about = "Apply pending schema migrations")]
+    Migrate {
+        #[structopt(
+            long = "status",
+            help = "Show applied and pending migrations without changing \
+                    anything"
+        )]
+        status: bool,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum RepoCmd {
+    #[structopt(
+        name = "add",
+        about = "Add a repository to the default prefix"
+    )]
+    Add {
+        #[structopt(short = "n", long = "name", help = "Repository name")]
+        name: Option<String>,
+        #[structopt(
+            short = "P",
+            long = "priority",
+            help = "Repository priority"
+        )]
+        priority: Option<i64>,
+        #[structopt(help = "Repository URL")]
+        url: String,
+    },
+    #[structopt(name = "disable", about = "Disable a repository")]
+    Disable {
+        #[structopt(help = "Repository name or URL")]
+        repo: String,
+    },
+    #[structopt(name = "enable", about = "Enable a repository")]
+    Enable {
+        #[structopt(help = "Repository name or URL")]
+        repo: String,
+    },
+    #[structopt(
+        name = "list",
+        alias = "ls",
+        about = "List configured repositories"
+    )]
+    List,
+    #[structopt(name = "remove", alias = "rm", about = "Remove a repository")]
+    Remove {
+        #[structopt(help = "Repository name or URL")]
+        repo: String,
+    },
+}
+
+/*
+ * Commands whose exit status reports a result use a separate status for
+ * errors, so that a failure is never mistaken for a result.
+ */
+fn error_status(cmd: &OptArgs) -> i32 {
+    match cmd.subcmd {
+        SubCmd::Audit => audit::EXIT_FAILED,
+        SubCmd::Licenses => license::EXIT_FAILED,
+        SubCmd::Update { check: true, .. } => update::EXIT_FAILED,
+        _ => 1,
+    }
+}
+
+fn main() {
     let cmd = OptArgs::from_args();
+    if let Err(e) = run(&cmd) {
+        eprintln!("ERROR: {}", e);
+        std::process::exit(error_status(&cmd));
+    }
+}
+
+/*
+ * Whether a command prints package records, and so supports --output and
+ * --format.
+ */
+fn prints_records(subcmd: &SubCmd) -> bool {
+    matches!(
+        subcmd,
+        SubCmd::Avail { .. }
+            | SubCmd::Info { .. }
+            | SubCmd::List
+            | SubCmd::Search { .. }
+    )
+}
+
+fn run(cmd: &OptArgs) -> Result<(), Box<dyn std::error::Error>> {
+    if (cmd.output != output::Format::Plain || cmd.format.is_some())
+        && !prints_records(&cmd.subcmd)
+    {
+        return Err("--output and --format are only supported by avail, \
+                    info, list, and search"
+            .into());
+    }
+
+    /* Checking a pkg_summary file needs neither configuration nor database */
+    if let SubCmd::LintSummary { file } = &cmd.subcmd {
+        summary::lint(file)?;
+        return Ok(());
+    }
 
     /* Pass cmd so that the user can override the default with -c */
-    let cfg = config::Config::load(&cmd)?;
+    let cfg = config::Config::load(cmd)?;
 
     let pmdb_file = dirs::data_dir().unwrap().join("pm.db");
-    let mut db = PMDB::new(&pmdb_file)?;
+
+    /*
+     * "update --check" must not modify the database, not even to create it,
+     * so it is handled before the database is opened normally.
+     */
+    if let SubCmd::Update {
+        check: true,
+        targets,
+        ..
+    } = &cmd.subcmd
+    {
+        let db = PMDB::open_readonly(&pmdb_file)?;
+        update::check(&cfg, db.as_ref(), targets)?;
+        return Ok(());
+    }
+
+    if let SubCmd::Db {
+        cmd: DbCmd::Migrate { status },
+    } = &cmd.subcmd
+    {
+        db::migrate(&pmdb_file, *status)?;
+        return Ok(());
+    }
+
+    if let SubCmd::VerifyPkg { file } = &cmd.subcmd {
+        verify::run(&cfg, file)?;
+        return Ok(());
+    }
+
+    let mut db = match PMDB::new(&pmdb_file) {
+        Ok(db) => db,
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            std::process::exit(1);
+        }
+    };
 
     match &cmd.subcmd {
-        SubCmd::Avail => {
-            list::avail(&cfg, &mut db)?;
+        SubCmd::Avail {
+            no_vulnerable,
+            acceptable,
+        } => {
+            list::avail(&cfg, &mut db, *no_vulnerable, *acceptable)?;
+        }
+        SubCmd::Audit => {
+            audit::run(&cfg, &mut db)?;
+        }
+        SubCmd::Info {
+            pkgname,
+            no_vulnerable,
+        } => {
+            info::run(&cfg, &mut db, pkgname, *no_vulnerable)?;
+        }
+        SubCmd::Licenses => {
+            license::run(&cfg, &mut db)?;
         }
         SubCmd::List => {
             list::list(&cfg, &mut db)?;
         }
-        SubCmd::Search { query } => {
-            search::run(&cfg, &mut db, &query)?;
+        SubCmd::Repo { cmd: repocmd } => {
+            let explicit_prefix = cmd.prefix.is_some();
+            match repocmd {
+                RepoCmd::Add {
+                    name,
+                    priority,
+                    url,
+                } => repo::add(&cfg, url, name, *priority)?,
+                RepoCmd::Disable { repo } => {
+                    repo::set_enabled(&cfg, repo, explicit_prefix, false)?
+                }
+                RepoCmd::Enable { repo } => {
+                    repo::set_enabled(&cfg, repo, explicit_prefix, true)?
+                }
+                RepoCmd::List => repo::list(&cfg, &mut db)?,
+                RepoCmd::Remove { repo } => {
+                    repo::remove(&cfg, &mut db, repo, explicit_prefix)?
+                }
+            }
+        }
+        SubCmd::Search {
+            query,
+            text,
+            comment,
+            description,
+            category,
+            pkgpath,
+            homepage,
+            license,
+            all,
+            exact,
+            glob,
+            fuzzy,
+            case_sensitive,
+            no_vulnerable,
+            acceptable,
+        } => {
+            let mode = if *exact {
+                search::Mode::Exact
+            } else if *glob {
+                search::Mode::Glob
+            } else if *fuzzy {
+                search::Mode::Fuzzy
+            } else {
+                search::Mode::Regex
+            };
+            let opts = search::Options {
+                query: query.clone(),
+                text: *text,
+                comment: comment.clone(),
+                description: description.clone(),
+                category: category.clone(),
+                pkgpath: pkgpath.clone(),
+                homepage: homepage.clone(),
+                license: license.clone(),
+                all: all.clone(),
+                mode,
+                case_sensitive: *case_sensitive,
+                no_vulnerable: *no_vulnerable,
+                acceptable: *acceptable,
+            };
+            search::run(&cfg, &mut db, &opts)?;
+        }
+        SubCmd::Update {
+            force,
+            changes_json,
+            save_changes,
+            targets,
+            ..
+        } => {
+            let opts = update::Options {
+                changes_json: *changes_json,
+                save_changes: save_changes.clone(),
+                force: *force,
+                targets: targets.clone(),
+            };
+            update::run(&cfg, &mut db, &opts)?;
         }
-        SubCmd::Update => {
-            update::run(&cfg, &mut db)?;
+        SubCmd::Db { .. }
+        | SubCmd::LintSummary { .. }
+        | SubCmd::VerifyPkg { .. } => {
+            unreachable!()
         }
     };
 
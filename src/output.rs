@@ -0,0 +1,496 @@
+/*
+ * Copyright (c) 2019 Jonathan Perkin <jonathan@perkin.org.uk>
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ *
+ * output.rs - machine-readable output of package lists, for --output and
+ * --format.
+ *
+ * Every command that lists packages writes the same Record for each one,
+ * whether the package is installed or available, so that the schema is the
+ * same everywhere.  Fields are only ever added to the end, never renamed or
+ * removed.
+ */
+
+use crate::config;
+use crate::list::PackageList;
+use serde_derive::Serialize;
+
+/*
+ * Output formats for --output.  "plain" is each command's normal output.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Plain,
+    Json,
+    Csv,
+    Tsv,
+}
+
+pub const FORMATS: &[&str] = &["plain", "json", "csv", "tsv"];
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "plain" => Ok(Format::Plain),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            "tsv" => Ok(Format::Tsv),
+            _ => Err(format!("unknown output format \"{}\"", s)),
+        }
+    }
+}
+
+/*
+ * A package as output.  Fields that only apply to installed or to available
+ * packages are null (or empty for CSV and TSV) for the other kind.
+ */
+#[derive(Debug, Serialize)]
+pub struct Record<'a> {
+    pub pkgname: &'a str,
+    pub pkgbase: &'a str,
+    pub pkgversion: &'a str,
+    pub comment: &'a str,
+    pub description: &'a str,
+    pub categories: &'a str,
+    pub homepage: &'a str,
+    pub license: &'a str,
+    pub pkgpath: &'a str,
+    pub build_date: &'a str,
+    pub opsys: &'a str,
+    pub os_version: &'a str,
+    pub pkg_options: &'a str,
+    pub pkgtools_version: &'a str,
+    pub size_pkg: i64,
+    pub file_name: Option<&'a str>,
+    pub file_size: Option<i64>,
+    pub automatic: Option<bool>,
+    pub repository: Option<&'a str>,
+    pub prefix: &'a str,
+    pub installed: bool,
+    pub available: bool,
+    pub vulnerable: bool,
+}
+
+/*
+ * Column names for CSV and TSV, in the same order as Record.
+ */
+const HEADER: &[&str] = &[
+    "pkgname",
+    "pkgbase",
+    "pkgversion",
+    "comment",
+    "description",
+    "categories",
+    "homepage",
+    "license",
+    "pkgpath",
+    "build_date",
+    "opsys",
+    "os_version",
+    "pkg_options",
+    "pkgtools_version",
+    "size_pkg",
+    "file_name",
+    "file_size",
+    "automatic",
+    "repository",
+    "prefix",
+    "installed",
+    "available",
+    "vulnerable",
+];
+
+impl<'a> Record<'a> {
+    /*
+     * A package installed in a prefix.
+     */
+    pub fn local(
+        pkg: &'a PackageList,
+        prefix: &'a str,
+        available: bool,
+        vulnerable: bool,
+    ) -> Record<'a> {
+        Record {
+            file_name: None,
+            file_size: None,
+            automatic: Some(*pkg.automatic()),
+            repository: None,
+            installed: true,
+            available,
+            ..Record::new(pkg, prefix, vulnerable)
+        }
+    }
+
+    /*
+     * A package available from a repository.
+     */
+    pub fn remote(
+        pkg: &'a PackageList,
+        prefix: &'a str,
+        installed: bool,
+        vulnerable: bool,
+    ) -> Record<'a> {
+        Record {
+            file_name: Some(pkg.file_name()),
+            file_size: Some(*pkg.file_size()),
+            automatic: None,
+            repository: Some(pkg.repository()),
+            installed,
+            available: true,
+            ..Record::new(pkg, prefix, vulnerable)
+        }
+    }
+
+    fn new(pkg: &'a PackageList, prefix: &'a str, vulnerable: bool) -> Self {
+        Record {
+            pkgname: pkg.pkgname(),
+            pkgbase: pkg.pkgbase(),
+            pkgversion: pkg.pkgversion(),
+            comment: pkg.comment(),
+            description: pkg.description(),
+            categories: pkg.categories(),
+            homepage: pkg.homepage(),
+            license: pkg.license(),
+            pkgpath: pkg.pkgpath(),
+            build_date: pkg.build_date(),
+            opsys: pkg.opsys(),
+            os_version: pkg.os_version(),
+            pkg_options: pkg.pkg_options(),
+            pkgtools_version: pkg.pkgtools_version(),
+            size_pkg: *pkg.size_pkg(),
+            file_name: None,
+            file_size: None,
+            automatic: None,
+            repository: None,
+            prefix,
+            installed: false,
+            available: false,
+            vulnerable,
+        }
+    }
+
+    /*
+     * The fields as strings, in the same order as HEADER.
+     */
+    fn values(&self) -> Vec<String> {
+        let opt = |v: Option<String>| v.unwrap_or_default();
+        vec![
+            self.pkgname.to_string(),
+            self.pkgbase.to_string(),
+            self.pkgversion.to_string(),
+            self.comment.to_string(),
+            self.description.to_string(),
+            self.categories.to_string(),
+            self.homepage.to_string(),
+            self.license.to_string(),
+            self.pkgpath.to_string(),
+            self.build_date.to_string(),
+            self.opsys.to_string(),
+            self.os_version.to_string(),
+            self.pkg_options.to_string(),
+            self.pkgtools_version.to_string(),
+            self.size_pkg.to_string(),
+            opt(self.file_name.map(str::to_string)),
+            opt(self.file_size.map(|s| s.to_string())),
+            opt(self.automatic.map(|a| a.to_string())),
+            opt(self.repository.map(str::to_string)),
+            self.prefix.to_string(),
+            self.installed.to_string(),
+            self.available.to_string(),
+            self.vulnerable.to_string(),
+        ]
+    }
+}
+
+/*
+ * Quote a CSV field as in RFC 4180 if it needs it.
+ */
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/*
+ * TSV fields can't contain tabs or newlines, so escape them with
+ * backslashes.
+ */
+fn tsv_field(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+/*
+ * Short placeholders for the most commonly used fields, as in pkg_info -Q
+ * and "pkg query".  Every field is available as %{name}.
+ */
+const SHORT_FIELDS: &[(char, &str)] = &[
+    ('n', "pkgbase"),
+    ('v', "pkgversion"),
+    ('N', "pkgname"),
+    ('c', "comment"),
+    ('d', "description"),
+    ('C', "categories"),
+    ('o', "pkgpath"),
+    ('w', "homepage"),
+    ('l', "license"),
+    ('s', "size_pkg"),
+    ('r', "repository"),
+    ('p', "prefix"),
+    ('i', "installed"),
+];
+
+#[derive(Clone, Debug)]
+enum Part {
+    Literal(String),
+    Field {
+        index: usize,
+        left: bool,
+        width: usize,
+        precision: Option<usize>,
+    },
+}
+
+/*
+ * A --format template.  Placeholders are "%" followed by an optional "-" to
+ * left align, a minimum width, a "." and maximum width, and then either a
+ * short field name or a full field name in braces, such as "%-20N" or
+ * "%.40{comment}".  "%%" is a literal "%", and "\n", "\t", and "\\" are
+ * escapes for a newline, tab, and backslash.
+ */
+#[derive(Clone, Debug)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+type Chars<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+fn digits(chars: &mut Chars) -> Option<usize> {
+    let mut n = String::new();
+    while let Some(d) = chars.next_if(char::is_ascii_digit) {
+        n.push(d);
+    }
+    n.parse().ok()
+}
+
+/*
+ * Parse a placeholder following a "%".
+ */
+fn placeholder(chars: &mut Chars) -> Result<Part, String> {
+    let left = chars.next_if_eq(&'-').is_some();
+    let width = digits(chars).unwrap_or(0);
+    let precision = match chars.next_if_eq(&'.') {
+        Some(_) => Some(
+            digits(chars).ok_or("missing precision after \".\"".to_string())?,
+        ),
+        None => None,
+    };
+    let name = match chars.next() {
+        Some('{') => {
+            let mut name = String::new();
+            loop {
+                match chars.next() {
+                    Some('}') => break,
+                    Some(c) => name.push(c),
+                    None => return Err("missing \"}\"".to_string()),
+                }
+            }
+            name
+        }
+        Some(c) => match SHORT_FIELDS.iter().find(|f| f.0 == c) {
+            Some((_, name)) => name.to_string(),
+            None => return Err(format!("unknown placeholder \"%{}\"", c)),
+        },
+        None => return Err("trailing \"%\"".to_string()),
+    };
+    match HEADER.iter().position(|h| *h == name) {
+        Some(index) => Ok(Part::Field {
+            index,
+            left,
+            width,
+            precision,
+        }),
+        None => Err(format!("unknown field \"{}\"", name)),
+    }
+}
+
+impl std::str::FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Template, String> {
+        let mut parts = vec![];
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some('n') => literal.push('\n'),
+                    Some('t') => literal.push('\t'),
+                    Some('\\') => literal.push('\\'),
+                    Some(c) => {
+                        return Err(format!("unknown escape \"\\{}\"", c))
+                    }
+                    None => return Err("trailing \"\\\"".to_string()),
+                },
+                '%' if chars.next_if_eq(&'%').is_some() => literal.push('%'),
+                '%' => {
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(placeholder(&mut chars)?);
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Template { parts })
+    }
+}
+
+impl Template {
+    fn render(&self, record: &Record) -> String {
+        let values = record.values();
+        let mut line = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(s) => line.push_str(s),
+                Part::Field {
+                    index,
+                    left,
+                    width,
+                    precision,
+                } => {
+                    let value: String = match precision {
+                        Some(p) => values[*index].chars().take(*p).collect(),
+                        None => values[*index].clone(),
+                    };
+                    let pad =
+                        " ".repeat(width.saturating_sub(value.chars().count()));
+                    if *left {
+                        line.push_str(&value);
+                        line.push_str(&pad);
+                    } else {
+                        line.push_str(&pad);
+                        line.push_str(&value);
+                    }
+                }
+            }
+        }
+        line
+    }
+}
+
+/*
+ * Return whether package lists should be printed as records rather than as
+ * each command's plain output.
+ */
+pub fn wanted(cfg: &config::Config) -> bool {
+    cfg.output() != Format::Plain || cfg.template().is_some()
+}
+
+/*
+ * Print records using the --format template, or in a machine-readable
+ * format.  JSON is an array of objects, CSV and TSV have a header line
+ * naming each column.
+ */
+pub fn print(
+    cfg: &config::Config,
+    records: &[Record],
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(template) = cfg.template() {
+        for record in records {
+            println!("{}", template.render(record));
+        }
+        return Ok(());
+    }
+    let (sep, quote): (&str, fn(&str) -> String) = match cfg.output() {
+        Format::Json => {
+            println!("{}", serde_json::to_string_pretty(records)?);
+            return Ok(());
+        }
+        Format::Csv => (",", csv_field),
+        Format::Tsv => ("\t", tsv_field),
+        Format::Plain => unreachable!(),
+    };
+    let header: Vec<String> = HEADER.iter().map(|h| quote(h)).collect();
+    println!("{}", header.join(sep));
+    for record in records {
+        let values: Vec<String> =
+            record.values().iter().map(|v| quote(v)).collect();
+        println!("{}", values.join(sep));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str) -> Result<String, String> {
+        let pkg = PackageList {
+            pkgname: "curl-7.65.0".to_string(),
+            pkgbase: "curl".to_string(),
+            pkgversion: "7.65.0".to_string(),
+            comment: "Client that groks URLs".to_string(),
+            ..Default::default()
+        };
+        let record = Record::remote(&pkg, "/opt/local", false, false);
+        template.parse::<Template>().map(|t| t.render(&record))
+    }
+
+    #[test]
+    fn fields() {
+        assert_eq!(render("%N"), Ok("curl-7.65.0".to_string()));
+        assert_eq!(render("%n %v"), Ok("curl 7.65.0".to_string()));
+        assert_eq!(render("%{pkgbase}/%p"), Ok("curl//opt/local".to_string()));
+        assert_eq!(render("%i"), Ok("false".to_string()));
+        assert_eq!(render(""), Ok("".to_string()));
+    }
+
+    #[test]
+    fn width_and_precision() {
+        assert_eq!(render("%8n|"), Ok("    curl|".to_string()));
+        assert_eq!(render("%-8n|"), Ok("curl    |".to_string()));
+        assert_eq!(render("%.6c"), Ok("Client".to_string()));
+        assert_eq!(render("%-8.3n|"), Ok("cur     |".to_string()));
+        assert_eq!(render("%2N"), Ok("curl-7.65.0".to_string()));
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(render("100%%"), Ok("100%".to_string()));
+        assert_eq!(render("%n\\t%v\\n"), Ok("curl\t7.65.0\n".to_string()));
+        assert_eq!(render("a\\\\b"), Ok("a\\b".to_string()));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(render("%X").is_err());
+        assert!(render("%{nope}").is_err());
+        assert!(render("%{pkgname").is_err());
+        assert!(render("%.n").is_err());
+        assert!(render("trailing %").is_err());
+        assert!(render("\\x").is_err());
+        assert!(render("trailing \\").is_err());
+    }
+}
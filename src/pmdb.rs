@@ -20,15 +20,18 @@ extern crate rusqlite;
 
 use crate::list::PackageList;
 use pkgsrc::SummaryEntry;
-use rusqlite::Connection;
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
 use std::fs;
 
+#[allow(clippy::upper_case_acronyms, dead_code)]
 #[derive(Debug)]
 pub struct PMDB {
     db: Connection,
     repositories: Vec<RemoteRepository>,
+    fts: bool,
 }
 
+#[allow(dead_code)]
 #[derive(Debug)]
 pub struct LocalRepository {
     prefix: String,
@@ -37,23 +40,454 @@ pub struct LocalRepository {
     need_update: bool,
 }
 
+#[allow(dead_code)]
 #[derive(Debug)]
 pub struct RemoteRepository {
+    id: i64,
     url: String,
+    name: String,
     mtime: i64,
     summary_suffix: String,
+    summary_digest: Option<String>,
+    updated: Option<i64>,
     need_update: bool,
 }
 
-#[cfg_attr(feature = "cargo-clippy", allow(clippy::unreadable_literal))]
-const DB_VERSION: i64 = 20190321;
+/*
+ * A schema migration.  Migrations are applied in order of version, each in
+ * its own transaction along with recording the new version in "metadata", so
+ * a migration that fails leaves the database as it was.  Once released a
+ * migration must never be changed, add a new one instead.
+ *
+ * Migrations that need FTS5 are recorded without being applied if SQLite
+ * was built without it, and are applied when pm is next run with an SQLite
+ * that has it.
+ */
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    sql: &'static str,
+    fts5: bool,
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Initial schema",
+        sql: "
+        CREATE TABLE local_repository (
+            id                  INTEGER PRIMARY KEY,
+            prefix              TEXT NOT NULL UNIQUE,
+            mtime               INTEGER NOT NULL,
+            ntime               INTEGER NOT NULL
+        );
+        CREATE TABLE local_pkg (
+            id                  INTEGER PRIMARY KEY,
+            repository_id       INTEGER NOT NULL,
+            automatic           INTEGER NOT NULL,
+            build_date          TEXT NOT NULL,
+            categories          TEXT NOT NULL,
+            comment             TEXT NOT NULL,
+            description         TEXT NOT NULL,
+            homepage            TEXT,
+            license             TEXT,
+            opsys               TEXT NOT NULL,
+            os_version          TEXT NOT NULL,
+            pkg_options         TEXT,
+            pkgbase             TEXT NOT NULL,
+            pkgname             TEXT NOT NULL,
+            pkgpath             TEXT NOT NULL,
+            pkgtools_version    TEXT NOT NULL,
+            pkgversion          TEXT NOT NULL,
+            size_pkg            INTEGER NOT NULL
+        );
+        CREATE TABLE local_conflicts (
+            id                  INTEGER PRIMARY KEY,
+            repository_id       INTEGER NOT NULL,
+            pkg_id              INTEGER NOT NULL,
+            conflicts           TEXT NOT NULL
+        );
+        CREATE TABLE local_depends (
+            id                  INTEGER PRIMARY KEY,
+            repository_id       INTEGER NOT NULL,
+            pkg_id              INTEGER NOT NULL,
+            depends             TEXT NOT NULL
+        );
+        CREATE TABLE local_provides (
+            id                  INTEGER PRIMARY KEY,
+            repository_id       INTEGER NOT NULL,
+            pkg_id              INTEGER NOT NULL,
+            provides            TEXT NOT NULL
+        );
+        CREATE TABLE local_requires (
+            id                  INTEGER PRIMARY KEY,
+            repository_id       INTEGER NOT NULL,
+            pkg_id              INTEGER NOT NULL,
+            requires            TEXT NOT NULL
+        );
+        CREATE TABLE vulnerability_source (
+            location            TEXT NOT NULL,
+            mtime               INTEGER NOT NULL,
+            checksum            TEXT NOT NULL
+        );
+        CREATE TABLE vulnerability (
+            id                  INTEGER PRIMARY KEY,
+            pattern             TEXT NOT NULL,
+            type                TEXT NOT NULL,
+            url                 TEXT NOT NULL
+        );
+        CREATE TABLE remote_repository (
+            id                  INTEGER PRIMARY KEY,
+            prefix              TEXT NOT NULL,
+            url                 TEXT NOT NULL UNIQUE,
+            name                TEXT NOT NULL,
+            summary_suffix      TEXT NOT NULL,
+            summary_digest      TEXT,
+            mtime               INTEGER NOT NULL
+        );
+        CREATE TABLE remote_pkg (
+            id                  INTEGER PRIMARY KEY,
+            repository_id       INTEGER NOT NULL,
+            build_date          TEXT NOT NULL,
+            categories          TEXT NOT NULL,
+            comment             TEXT NOT NULL,
+            description         TEXT NOT NULL,
+            file_name           TEXT,
+            file_size           INTEGER NOT NULL,
+            homepage            TEXT,
+            license             TEXT,
+            opsys               TEXT NOT NULL,
+            os_version          TEXT NOT NULL,
+            pkg_options         TEXT,
+            pkgbase             TEXT NOT NULL,
+            pkgname             TEXT NOT NULL,
+            pkgpath             TEXT NOT NULL,
+            pkgtools_version    TEXT NOT NULL,
+            pkgversion          TEXT NOT NULL,
+            size_pkg            INTEGER NOT NULL
+        );
+        CREATE TABLE remote_conflicts (
+            id                  INTEGER PRIMARY KEY,
+            repository_id       INTEGER NOT NULL,
+            pkg_id              INTEGER NOT NULL,
+            conflicts           TEXT NOT NULL
+        );
+        CREATE TABLE remote_depends (
+            id                  INTEGER PRIMARY KEY,
+            repository_id       INTEGER NOT NULL,
+            pkg_id              INTEGER NOT NULL,
+            depends             TEXT NOT NULL
+        );
+        CREATE TABLE remote_provides (
+            id                  INTEGER PRIMARY KEY,
+            repository_id       INTEGER NOT NULL,
+            pkg_id              INTEGER NOT NULL,
+            provides            TEXT NOT NULL
+        );
+        CREATE TABLE remote_requires (
+            id                  INTEGER PRIMARY KEY,
+            repository_id       INTEGER NOT NULL,
+            pkg_id              INTEGER NOT NULL,
+            requires            TEXT NOT NULL
+        );
+    ",
+        fts5: false,
+    },
+    /*
+     * SQLite can't add constraints to existing tables, so every package
+     * table is recreated with foreign keys, dropping any orphaned rows that
+     * would violate them.  Conflicts, depends, provides, and requires belong
+     * to their package, and are deleted along with it.
+     */
+    Migration {
+        version: 2,
+        description: "Add foreign keys and indexes",
+        sql: "
+        CREATE TABLE new_local_pkg (
+            id                  INTEGER PRIMARY KEY,
+            repository_id       INTEGER NOT NULL
+                                REFERENCES local_repository (id)
+                                ON DELETE CASCADE,
+            automatic           INTEGER NOT NULL,
+            build_date          TEXT NOT NULL,
+            categories          TEXT NOT NULL,
+            comment             TEXT NOT NULL,
+            description         TEXT NOT NULL,
+            homepage            TEXT,
+            license             TEXT,
+            opsys               TEXT NOT NULL,
+            os_version          TEXT NOT NULL,
+            pkg_options         TEXT,
+            pkgbase             TEXT NOT NULL,
+            pkgname             TEXT NOT NULL,
+            pkgpath             TEXT NOT NULL,
+            pkgtools_version    TEXT NOT NULL,
+            pkgversion          TEXT NOT NULL,
+            size_pkg            INTEGER NOT NULL
+        );
+        INSERT INTO new_local_pkg
+            SELECT * FROM local_pkg
+             WHERE repository_id IN (SELECT id FROM local_repository);
+        DROP TABLE local_pkg;
+        ALTER TABLE new_local_pkg RENAME TO local_pkg;
+        CREATE TABLE new_local_conflicts (
+            id                  INTEGER PRIMARY KEY,
+            repository_id       INTEGER NOT NULL,
+            pkg_id              INTEGER NOT NULL
+                                REFERENCES local_pkg (id)
+                                ON DELETE CASCADE,
+            conflicts           TEXT NOT NULL
+        );
+        INSERT INTO new_local_conflicts
+            SELECT * FROM local_conflicts
+             WHERE pkg_id IN (SELECT id FROM local_pkg);
+        DROP TABLE local_conflicts;
+        ALTER TABLE new_local_conflicts RENAME TO local_conflicts;
+        CREATE TABLE new_local_depends (
+            id                  INTEGER PRIMARY KEY,
+            repository_id       INTEGER NOT NULL,
+            pkg_id              INTEGER NOT NULL
+                                REFERENCES local_pkg (id)
+                                ON DELETE CASCADE,
+            depends             TEXT NOT NULL
+        );
+        INSERT INTO new_local_depends
+            SELECT * FROM local_depends
+             WHERE pkg_id IN (SELECT id FROM local_pkg);
+        DROP TABLE local_depends;
+        ALTER TABLE new_local_depends RENAME TO local_depends;
+        CREATE TABLE new_local_provides (
+            id                  INTEGER PRIMARY KEY,
+            repository_id       INTEGER NOT NULL,
+            pkg_id              INTEGER NOT NULL
+                                REFERENCES local_pkg (id)
+                                ON DELETE CASCADE,
+            provides            TEXT NOT NULL
+        );
+        INSERT INTO new_local_provides
+            SELECT * FROM local_provides
+             WHERE pkg_id IN (SELECT id FROM local_pkg);
+        DROP TABLE local_provides;
+        ALTER TABLE new_local_provides RENAME TO local_provides;
+        CREATE TABLE new_local_requires (
+            id                  INTEGER PRIMARY KEY,
+            repository_id       INTEGER NOT NULL,
+            pkg_id              INTEGER NOT NULL
+                                REFERENCES local_pkg (id)
+                                ON DELETE CASCADE,
+            requires            TEXT NOT NULL
+        );
+        INSERT INTO new_local_requires
+            SELECT * FROM local_requires
+             WHERE pkg_id IN (SELECT id FROM local_pkg);
+        DROP TABLE local_requires;
+        ALTER TABLE new_local_requires RENAME TO local_requires;
+        CREATE TABLE new_remote_pkg (
+            id                  INTEGER PRIMARY KEY,
+            repository_id       INTEGER NOT NULL
+                                REFERENCES remote_repository (id)
+                                ON DELETE CASCADE,
+            build_date          TEXT NOT NULL,
+            categories          TEXT NOT NULL,
+            comment             TEXT NOT NULL,
+            description         TEXT NOT NULL,
+            file_name           TEXT,
+            file_size           INTEGER NOT NULL,
+            homepage            TEXT,
+            license             TEXT,
+            opsys               TEXT NOT NULL,
+            os_version          TEXT NOT NULL,
+            pkg_options         TEXT,
+            pkgbase             TEXT NOT NULL,
+            pkgname             TEXT NOT NULL,
+            pkgpath             TEXT NOT NULL,
+            pkgtools_version    TEXT NOT NULL,
+            pkgversion          TEXT NOT NULL,
+            size_pkg            INTEGER NOT NULL
+        );
+        INSERT INTO new_remote_pkg
+            SELECT * FROM remote_pkg
+             WHERE repository_id IN (SELECT id FROM remote_repository);
+        DROP TABLE remote_pkg;
+        ALTER TABLE new_remote_pkg RENAME TO remote_pkg;
+        CREATE TABLE new_remote_conflicts (
+            id                  INTEGER PRIMARY KEY,
+            repository_id       INTEGER NOT NULL,
+            pkg_id              INTEGER NOT NULL
+                                REFERENCES remote_pkg (id)
+                                ON DELETE CASCADE,
+            conflicts           TEXT NOT NULL
+        );
+        INSERT INTO new_remote_conflicts
+            SELECT * FROM remote_conflicts
+             WHERE pkg_id IN (SELECT id FROM remote_pkg);
+        DROP TABLE remote_conflicts;
+        ALTER TABLE new_remote_conflicts RENAME TO remote_conflicts;
+        CREATE TABLE new_remote_depends (
+            id                  INTEGER PRIMARY KEY,
+            repository_id       INTEGER NOT NULL,
+            pkg_id              INTEGER NOT NULL
+                                REFERENCES remote_pkg (id)
+                                ON DELETE CASCADE,
+            depends             TEXT NOT NULL
+        );
+        INSERT INTO new_remote_depends
+            SELECT * FROM remote_depends
+             WHERE pkg_id IN (SELECT id FROM remote_pkg);
+        DROP TABLE remote_depends;
+        ALTER TABLE new_remote_depends RENAME TO remote_depends;
+        CREATE TABLE new_remote_provides (
+            id                  INTEGER PRIMARY KEY,
+            repository_id       INTEGER NOT NULL,
+            pkg_id              INTEGER NOT NULL
+                                REFERENCES remote_pkg (id)
+                                ON DELETE CASCADE,
+            provides            TEXT NOT NULL
+        );
+        INSERT INTO new_remote_provides
+            SELECT * FROM remote_provides
+             WHERE pkg_id IN (SELECT id FROM remote_pkg);
+        DROP TABLE remote_provides;
+        ALTER TABLE new_remote_provides RENAME TO remote_provides;
+        CREATE TABLE new_remote_requires (
+            id                  INTEGER PRIMARY KEY,
+            repository_id       INTEGER NOT NULL,
+            pkg_id              INTEGER NOT NULL
+                                REFERENCES remote_pkg (id)
+                                ON DELETE CASCADE,
+            requires            TEXT NOT NULL
+        );
+        INSERT INTO new_remote_requires
+            SELECT * FROM remote_requires
+             WHERE pkg_id IN (SELECT id FROM remote_pkg);
+        DROP TABLE remote_requires;
+        ALTER TABLE new_remote_requires RENAME TO remote_requires;
+        CREATE INDEX local_pkg_repository_id ON local_pkg (repository_id);
+        CREATE INDEX local_pkg_pkgbase ON local_pkg (pkgbase);
+        CREATE INDEX local_pkg_pkgname ON local_pkg (pkgname);
+        CREATE INDEX local_conflicts_pkg_id ON local_conflicts (pkg_id);
+        CREATE INDEX local_depends_pkg_id ON local_depends (pkg_id);
+        CREATE INDEX local_provides_pkg_id ON local_provides (pkg_id);
+        CREATE INDEX local_requires_pkg_id ON local_requires (pkg_id);
+        CREATE INDEX remote_pkg_repository_id ON remote_pkg (repository_id);
+        CREATE INDEX remote_pkg_pkgbase ON remote_pkg (pkgbase);
+        CREATE INDEX remote_pkg_pkgname ON remote_pkg (pkgname);
+        CREATE INDEX remote_conflicts_pkg_id ON remote_conflicts (pkg_id);
+        CREATE INDEX remote_depends_pkg_id ON remote_depends (pkg_id);
+        CREATE INDEX remote_provides_pkg_id ON remote_provides (pkg_id);
+        CREATE INDEX remote_requires_pkg_id ON remote_requires (pkg_id);
+        CREATE INDEX remote_repository_prefix ON remote_repository (prefix);
+    ",
+        fts5: false,
+    },
+    /*
+     * Full-text index for "search --text".  The text is read from remote_pkg
+     * rather than stored twice, so the index has to be kept in sync by hand
+     * whenever packages are inserted or deleted.
+     */
+    Migration {
+        version: 3,
+        description: "Add full-text index of remote packages",
+        sql: "
+        CREATE VIRTUAL TABLE remote_pkg_fts USING fts5 (
+            pkgname,
+            comment,
+            description,
+            categories,
+            content = 'remote_pkg',
+            content_rowid = 'id',
+            tokenize = 'porter unicode61'
+        );
+        INSERT INTO remote_pkg_fts (remote_pkg_fts) VALUES ('rebuild');
+    ",
+        fts5: true,
+    },
+    /*
+     * "mtime" is the Last-Modified time of pkg_summary, record separately
+     * when pm last updated or checked each repository.
+     */
+    Migration {
+        version: 4,
+        description: "Add last update time of remote repositories",
+        sql: "
+        ALTER TABLE remote_repository ADD COLUMN updated INTEGER;
+    ",
+        fts5: false,
+    },
+];
+
+/*
+ * Before migrations the schema version was a date, and the database was
+ * recreated whenever it changed.  The last of these is the same schema as
+ * migration 1, anything older is still recreated.
+ */
+#[allow(clippy::unreadable_literal)]
+const LEGACY_VERSION: i64 = 20190804;
+#[allow(clippy::unreadable_literal)]
+const LEGACY_MIN_VERSION: i64 = 20000000;
+
+/*
+ * The columns of a remote package needed to choose between copies of it and
+ * list it, in the order remote_pkg_from_row() expects them, for queries that
+ * join remote_pkg and remote_repository.
+ */
+const REMOTE_PKG_COLUMNS: &str = "
+    remote_pkg.id, repository_id, remote_repository.name, comment, license,
+    pkgbase, pkgname, pkgversion";
+
+/*
+ * Every other column, following REMOTE_PKG_COLUMNS in the order
+ * remote_pkg_details_from_row() expects them.  These include DESCRIPTION,
+ * so are only loaded when needed.
+ */
+const REMOTE_PKG_DETAIL_COLUMNS: &str = "
+    build_date, categories, description, file_name, file_size, homepage,
+    opsys, os_version, pkg_options, pkgpath, pkgtools_version, size_pkg";
+
+type RemotePkgFromRow = fn(&rusqlite::Row) -> rusqlite::Result<PackageList>;
+
+fn remote_pkg_from_row(row: &rusqlite::Row) -> rusqlite::Result<PackageList> {
+    Ok(PackageList {
+        id: row.get(0)?,
+        repository_id: row.get(1)?,
+        repository: row.get(2)?,
+        comment: row.get(3)?,
+        license: row.get::<_, Option<String>>(4)?.unwrap_or_default(),
+        pkgbase: row.get(5)?,
+        pkgname: row.get(6)?,
+        pkgversion: row.get(7)?,
+        ..Default::default()
+    })
+}
+
+fn remote_pkg_details_from_row(
+    row: &rusqlite::Row,
+) -> rusqlite::Result<PackageList> {
+    Ok(PackageList {
+        build_date: row.get(8)?,
+        categories: row.get(9)?,
+        description: row.get(10)?,
+        file_name: row.get::<_, Option<String>>(11)?.unwrap_or_default(),
+        file_size: row.get(12)?,
+        homepage: row.get::<_, Option<String>>(13)?.unwrap_or_default(),
+        opsys: row.get(14)?,
+        os_version: row.get(15)?,
+        pkg_options: row.get::<_, Option<String>>(16)?.unwrap_or_default(),
+        pkgpath: row.get(17)?,
+        pkgtools_version: row.get(18)?,
+        size_pkg: row.get(19)?,
+        ..remote_pkg_from_row(row)?
+    })
+}
 
 impl PMDB {
     /*
      * Open a new connection to the database and perform any necessary setup
      * prior to returning.
      */
-    pub fn new(p: &std::path::Path) -> rusqlite::Result<PMDB> {
+    pub fn new(
+        p: &std::path::Path,
+    ) -> Result<PMDB, Box<dyn std::error::Error>> {
         fs::create_dir_all(
             p.parent().expect("Could not determine database path"),
         )
@@ -66,173 +500,196 @@ impl PMDB {
          */
         db.execute("PRAGMA synchronous = EXTRA;", rusqlite::NO_PARAMS)?;
 
-        if !PMDB::is_created(&db)? {
-            PMDB::drop_default_tables(&mut db)?;
-            PMDB::create_default_tables(&mut db)?;
-        } else if !PMDB::is_current(&db)? {
-            PMDB::drop_default_tables(&mut db)?;
-            PMDB::create_default_tables(&mut db)?;
-        }
+        PMDB::migrate(&mut db)?;
+        db.execute("PRAGMA foreign_keys = ON;", rusqlite::NO_PARAMS)?;
+        let fts = PMDB::has_fts(&db)?;
 
         Ok(PMDB {
             db,
             repositories: Vec::new(),
+            fts,
         })
     }
 
     /*
-     * Test for the existance of the "metadata" table to determine if
-     * we need to create the initial set of tables or not.
+     * Open an existing database without modifying it in any way, for
+     * commands that must leave it untouched.  Returns None if there is no
+     * database yet, or it uses an older schema, as in both cases everything
+     * needs updating anyway.
      */
-    fn is_created(db: &Connection) -> rusqlite::Result<bool> {
+    pub fn open_readonly(
+        p: &std::path::Path,
+    ) -> rusqlite::Result<Option<PMDB>> {
+        if !p.exists() {
+            return Ok(None);
+        }
+        let db =
+            Connection::open_with_flags(p, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        if PMDB::schema_version(&db)? != Some(PMDB::latest_version()) {
+            return Ok(None);
+        }
+        let fts = PMDB::has_fts(&db)?;
+        Ok(Some(PMDB {
+            db,
+            repositories: Vec::new(),
+            fts,
+        }))
+    }
+
+    /*
+     * Return whether the SQLite library supports FTS5, which is needed for
+     * the full-text index used by "search --text".
+     */
+    fn fts5_available(db: &Connection) -> rusqlite::Result<bool> {
+        db.query_row(
+            "SELECT sqlite_compileoption_used('ENABLE_FTS5')",
+            rusqlite::NO_PARAMS,
+            |r| r.get(0),
+        )
+    }
+
+    /*
+     * Return whether the full-text index exists.
+     */
+    fn has_fts(db: &Connection) -> rusqlite::Result<bool> {
         let count: i64 = db.query_row(
             "SELECT COUNT(*)
                FROM sqlite_master
               WHERE type='table'
-                AND name='metadata'",
+                AND name='remote_pkg_fts'",
             rusqlite::NO_PARAMS,
             |r| r.get(0),
         )?;
         Ok(count > 0)
     }
 
-    fn is_current(db: &Connection) -> rusqlite::Result<bool> {
-        let current: i64 = db.query_row(
+    /*
+     * Whether "search --text" can be used.
+     */
+    pub fn fts(&self) -> bool {
+        self.fts
+    }
+
+    /*
+     * Return the schema version recorded in "metadata", or None if it
+     * doesn't exist yet.
+     */
+    fn schema_version(db: &Connection) -> rusqlite::Result<Option<i64>> {
+        let count: i64 = db.query_row(
+            "SELECT COUNT(*)
+               FROM sqlite_master
+              WHERE type='table'
+                AND name='metadata'",
+            rusqlite::NO_PARAMS,
+            |r| r.get(0),
+        )?;
+        if count == 0 {
+            return Ok(None);
+        }
+        db.query_row(
             "SELECT version
                FROM metadata",
             rusqlite::NO_PARAMS,
             |r| r.get(0),
-        )?;
-        Ok(current == DB_VERSION)
+        )
+        .optional()
     }
 
     /*
-     * Create the default set of tables.  For now upgrades aren't supported,
-     * we simply drop everything and start again on schema changes.
-     *
-     * XXX: I don't understand why using a transaction means I'm forced to
-     * make the whole thing mutable, would prefer to avoid that.
+     * Return the last migration applied to the database at a path without
+     * changing it, or None if there is no database yet.  Databases from
+     * before migrations are reported as they will be treated.
      */
-    pub fn create_default_tables(db: &mut Connection) -> rusqlite::Result<()> {
-        let tx = db.transaction()?;
-        tx.execute_batch(
-            "
-            CREATE TABLE metadata (
-                version             INTEGER NOT NULL
-            );
-            CREATE TABLE local_repository (
-                id                  INTEGER PRIMARY KEY,
-                prefix              TEXT NOT NULL UNIQUE,
-                mtime               INTEGER NOT NULL,
-                ntime               INTEGER NOT NULL
-            );
-            CREATE TABLE local_pkg (
-                id                  INTEGER PRIMARY KEY,
-                repository_id       INTEGER NOT NULL,
-                automatic           INTEGER NOT NULL,
-                build_date          TEXT NOT NULL,
-                categories          TEXT NOT NULL,
-                comment             TEXT NOT NULL,
-                description         TEXT NOT NULL,
-                homepage            TEXT,
-                license             TEXT,
-                opsys               TEXT NOT NULL,
-                os_version          TEXT NOT NULL,
-                pkg_options         TEXT,
-                pkgbase             TEXT NOT NULL,
-                pkgname             TEXT NOT NULL,
-                pkgpath             TEXT NOT NULL,
-                pkgtools_version    TEXT NOT NULL,
-                pkgversion          TEXT NOT NULL,
-                size_pkg            INTEGER NOT NULL
-            );
-            CREATE TABLE local_conflicts (
-                id                  INTEGER PRIMARY KEY,
-                repository_id       INTEGER NOT NULL,
-                pkg_id              INTEGER NOT NULL,
-                conflicts           TEXT NOT NULL
-            );
-            CREATE TABLE local_depends (
-                id                  INTEGER PRIMARY KEY,
-                repository_id       INTEGER NOT NULL,
-                pkg_id              INTEGER NOT NULL,
-                depends             TEXT NOT NULL
-            );
-            CREATE TABLE local_provides (
-                id                  INTEGER PRIMARY KEY,
-                repository_id       INTEGER NOT NULL,
-                pkg_id              INTEGER NOT NULL,
-                provides            TEXT NOT NULL
-            );
-            CREATE TABLE local_requires (
-                id                  INTEGER PRIMARY KEY,
-                repository_id       INTEGER NOT NULL,
-                pkg_id              INTEGER NOT NULL,
-                requires            TEXT NOT NULL
-            );
-            CREATE TABLE remote_repository (
-                id                  INTEGER PRIMARY KEY,
-                prefix              TEXT NOT NULL,
-                url                 TEXT NOT NULL UNIQUE,
-                summary_suffix      TEXT NOT NULL,
-                mtime               INTEGER NOT NULL
-            );
-            CREATE TABLE remote_pkg (
-                id                  INTEGER PRIMARY KEY,
-                repository_id       INTEGER NOT NULL,
-                build_date          TEXT NOT NULL,
-                categories          TEXT NOT NULL,
-                comment             TEXT NOT NULL,
-                description         TEXT NOT NULL,
-                file_name           TEXT,
-                file_size           INTEGER NOT NULL,
-                homepage            TEXT,
-                license             TEXT,
-                opsys               TEXT NOT NULL,
-                os_version          TEXT NOT NULL,
-                pkg_options         TEXT,
-                pkgbase             TEXT NOT NULL,
-                pkgname             TEXT NOT NULL,
-                pkgpath             TEXT NOT NULL,
-                pkgtools_version    TEXT NOT NULL,
-                pkgversion          TEXT NOT NULL,
-                size_pkg            INTEGER NOT NULL
-            );
-            CREATE TABLE remote_conflicts (
-                id                  INTEGER PRIMARY KEY,
-                repository_id       INTEGER NOT NULL,
-                pkg_id              INTEGER NOT NULL,
-                conflicts           TEXT NOT NULL
-            );
-            CREATE TABLE remote_depends (
-                id                  INTEGER PRIMARY KEY,
-                repository_id       INTEGER NOT NULL,
-                pkg_id              INTEGER NOT NULL,
-                depends             TEXT NOT NULL
-            );
-            CREATE TABLE remote_provides (
-                id                  INTEGER PRIMARY KEY,
-                repository_id       INTEGER NOT NULL,
-                pkg_id              INTEGER NOT NULL,
-                provides            TEXT NOT NULL
-            );
-            CREATE TABLE remote_requires (
-                id                  INTEGER PRIMARY KEY,
-                repository_id       INTEGER NOT NULL,
-                pkg_id              INTEGER NOT NULL,
-                requires            TEXT NOT NULL
-            );
-            ",
-        )?;
-        {
-            let mut stmt = tx.prepare(
-                "REPLACE INTO metadata
-                         (version)
-                  VALUES (:version)",
+    pub fn schema_version_at(
+        p: &std::path::Path,
+    ) -> rusqlite::Result<Option<i64>> {
+        if !p.exists() {
+            return Ok(None);
+        }
+        let db =
+            Connection::open_with_flags(p, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        Ok(match PMDB::schema_version(&db)? {
+            Some(LEGACY_VERSION) => Some(1),
+            Some(v) if v >= LEGACY_MIN_VERSION => Some(0),
+            v => v,
+        })
+    }
+
+    pub fn latest_version() -> i64 {
+        MIGRATIONS.last().map_or(0, |m| m.version)
+    }
+
+    /*
+     * Bring the schema up to date by applying any pending migrations.
+     */
+    fn migrate(db: &mut Connection) -> Result<(), Box<dyn std::error::Error>> {
+        let version = match PMDB::schema_version(db)? {
+            Some(LEGACY_VERSION) => {
+                db.execute(
+                    "UPDATE metadata SET version = 1",
+                    rusqlite::NO_PARAMS,
+                )?;
+                1
+            }
+            Some(v) if v >= LEGACY_MIN_VERSION => {
+                PMDB::drop_default_tables(db)?;
+                0
+            }
+            Some(v) => v,
+            None => 0,
+        };
+        if version > PMDB::latest_version() {
+            return Err(format!(
+                "Database version {} is newer than this pm supports ({})",
+                version,
+                PMDB::latest_version()
+            )
+            .into());
+        }
+        if version == 0 {
+            db.execute_batch(
+                "CREATE TABLE IF NOT EXISTS metadata (
+                     version             INTEGER NOT NULL
+                 );
+                 DELETE FROM metadata;
+                 INSERT INTO metadata (version) VALUES (0);",
             )?;
-            stmt.execute_named(&[(":version", &DB_VERSION)])?;
         }
-        tx.commit()
+
+        let fts5 = PMDB::fts5_available(db)?;
+        for m in MIGRATIONS.iter().filter(|m| m.version > version) {
+            let tx = db.transaction()?;
+            let sql = if m.fts5 && !fts5 { "" } else { m.sql };
+            let result = tx.execute_batch(sql).and_then(|_| {
+                tx.execute("UPDATE metadata SET version = ?", [m.version])
+            });
+            if let Err(e) = result {
+                return Err(format!(
+                    "Database migration {} ({}) failed: {}",
+                    m.version, m.description, e
+                )
+                .into());
+            }
+            tx.commit()?;
+        }
+
+        /*
+         * Apply any migrations skipped before SQLite supported FTS5.
+         */
+        if fts5 && !PMDB::has_fts(db)? {
+            for m in MIGRATIONS.iter().filter(|m| m.fts5) {
+                let tx = db.transaction()?;
+                if let Err(e) = tx.execute_batch(m.sql) {
+                    return Err(format!(
+                        "Database migration {} ({}) failed: {}",
+                        m.version, m.description, e
+                    )
+                    .into());
+                }
+                tx.commit()?;
+            }
+        }
+        Ok(())
     }
 
     pub fn drop_default_tables(db: &mut Connection) -> rusqlite::Result<()> {
@@ -252,6 +709,8 @@ impl PMDB {
             DROP TABLE IF EXISTS remote_depends;
             DROP TABLE IF EXISTS remote_provides;
             DROP TABLE IF EXISTS remote_requires;
+            DROP TABLE IF EXISTS vulnerability_source;
+            DROP TABLE IF EXISTS vulnerability;
         ",
         )?;
         tx.commit()
@@ -285,7 +744,8 @@ impl PMDB {
         url: &str,
     ) -> rusqlite::Result<Option<RemoteRepository>> {
         let mut stmt = self.db.prepare(
-            "SELECT mtime, summary_suffix
+            "SELECT id, name, mtime, summary_suffix, summary_digest,
+                    updated
                FROM remote_repository
               WHERE url = :url",
         )?;
@@ -293,9 +753,13 @@ impl PMDB {
         match rows.next()? {
             Some(row) => {
                 Ok(Some(RemoteRepository {
+                    id: row.get(0)?,
                     url: url.to_string(),
-                    mtime: row.get(0)?,
-                    summary_suffix: row.get(1)?,
+                    name: row.get(1)?,
+                    mtime: row.get(2)?,
+                    summary_suffix: row.get(3)?,
+                    summary_digest: row.get(4)?,
+                    updated: row.get(5)?,
                     need_update: false,
                 }))
             }
@@ -303,6 +767,35 @@ impl PMDB {
         }
     }
 
+    pub fn get_remote_repositories_by_prefix(
+        &self,
+        prefix: &str,
+    ) -> rusqlite::Result<Vec<RemoteRepository>> {
+        let mut result = Vec::new();
+        let mut stmt = self.db.prepare(
+            "SELECT id, url, name, mtime, summary_suffix, summary_digest,
+                    updated
+               FROM remote_repository
+              WHERE prefix = :prefix",
+        )?;
+        let rows = stmt.query_map_named(&[(":prefix", &prefix)], |row| {
+            Ok(RemoteRepository {
+                id: row.get(0)?,
+                url: row.get(1)?,
+                name: row.get(2)?,
+                mtime: row.get(3)?,
+                summary_suffix: row.get(4)?,
+                summary_digest: row.get(5)?,
+                updated: row.get(6)?,
+                need_update: false,
+            })
+        })?;
+        for row in rows {
+            result.push(row?)
+        }
+        Ok(result)
+    }
+
     fn insert_local_pkgs(
         tx: &rusqlite::Transaction,
         repository_id: i64,
@@ -341,8 +834,8 @@ impl PMDB {
         )?;
         for p in pkgs {
             /*
-             * These values have all been checked earlier when inserted so
-             * we are safe to unwrap.
+             * Entries have been validated by summary::Parser, so all of the
+             * required fields are present.
              */
             insert_pkg.execute_named(&[
                 (":repository_id", &repository_id),
@@ -361,7 +854,7 @@ impl PMDB {
                 (":pkgpath", &p.pkgpath()),
                 (":pkgtools_version", &p.pkgtools_version()),
                 (":pkgversion", &p.pkgversion()),
-                (":size_pkg", &(p.size_pkg().unwrap())),
+                (":size_pkg", &p.size_pkg()),
             ])?;
             let pkg_id = tx.last_insert_rowid();
             if !p.conflicts().is_empty() {
@@ -408,6 +901,8 @@ impl PMDB {
         tx: &rusqlite::Transaction,
         repository_id: i64,
         pkgs: &[SummaryEntry],
+        fts: bool,
+        progress: &mut dyn FnMut(usize),
     ) -> rusqlite::Result<()> {
         let mut insert_pkg = tx.prepare(
             "INSERT INTO remote_pkg
@@ -420,6 +915,16 @@ impl PMDB {
                      :opsys, :os_version, :pkg_options, :pkgbase, :pkgname,
                      :pkgpath, :pkgtools_version, :pkgversion, :size_pkg)",
         )?;
+        let mut insert_fts = if fts {
+            Some(tx.prepare(
+                "INSERT INTO remote_pkg_fts
+                        (rowid, pkgname, comment, description, categories)
+                 VALUES (:rowid, :pkgname, :comment, :description,
+                         :categories)",
+            )?)
+        } else {
+            None
+        };
         let mut insert_conflicts = tx.prepare(
             "INSERT INTO remote_conflicts
                     (repository_id, pkg_id, conflicts)
@@ -441,10 +946,12 @@ impl PMDB {
              VALUES (:repository_id, :pkg_id, :requires)",
         )?;
 
-        for p in pkgs {
+        for (i, p) in pkgs.iter().enumerate() {
+            progress(i);
+
             /*
-             * These values have all been checked earlier when inserted so
-             * we are safe to unwrap.
+             * Entries have been validated by summary::Parser, so all of the
+             * required fields are present.
              */
             insert_pkg.execute_named(&[
                 (":repository_id", &repository_id),
@@ -464,9 +971,18 @@ impl PMDB {
                 (":pkgpath", &p.pkgpath()),
                 (":pkgtools_version", &p.pkgtools_version()),
                 (":pkgversion", &p.pkgversion()),
-                (":size_pkg", &(p.size_pkg().unwrap())),
+                (":size_pkg", &p.size_pkg()),
             ])?;
             let pkg_id = tx.last_insert_rowid();
+            if let Some(insert_fts) = insert_fts.as_mut() {
+                insert_fts.execute_named(&[
+                    (":rowid", &pkg_id),
+                    (":pkgname", &p.pkgname()),
+                    (":comment", &p.comment()),
+                    (":description", &p.description().join("\n")),
+                    (":categories", &p.categories().join(" ")),
+                ])?;
+            }
             if !p.conflicts().is_empty() {
                 for conflicts in p.conflicts() {
                     insert_conflicts.execute_named(&[
@@ -507,45 +1023,47 @@ impl PMDB {
         Ok(())
     }
 
+    /*
+     * Deleting packages also deletes their conflicts, depends, provides,
+     * and requires, as the foreign keys cascade using the pkg_id indexes.
+     */
     fn delete_local_pkgs(
         tx: &rusqlite::Transaction,
         repository_id: i64,
     ) -> rusqlite::Result<()> {
-        let delete_tables = [
-            "local_pkg",
-            "local_conflicts",
-            "local_depends",
-            "local_provides",
-            "local_requires",
-        ];
-        for table in &delete_tables {
-            let sql = format!(
-                "DELETE FROM {} WHERE repository_id = :repository_id",
-                &table
-            );
-            tx.execute_named(&sql, &[(":repository_id", &repository_id)])?;
-        }
+        tx.execute_named(
+            "DELETE FROM local_pkg WHERE repository_id = :repository_id",
+            &[(":repository_id", &repository_id)],
+        )?;
         Ok(())
     }
 
+    /*
+     * As with local packages, except that the full-text index doesn't take
+     * part in the cascade, and removing a row from it needs the indexed text,
+     * so do that first.
+     */
     fn delete_remote_pkgs(
         tx: &rusqlite::Transaction,
         repository_id: i64,
+        fts: bool,
     ) -> rusqlite::Result<()> {
-        let delete_tables = [
-            "remote_pkg",
-            "remote_conflicts",
-            "remote_depends",
-            "remote_provides",
-            "remote_requires",
-        ];
-        for table in &delete_tables {
-            let sql = format!(
-                "DELETE FROM {} WHERE repository_id = :repository_id",
-                &table
-            );
-            tx.execute_named(&sql, &[(":repository_id", &repository_id)])?;
+        if fts {
+            tx.execute_named(
+                "INSERT INTO remote_pkg_fts
+                        (remote_pkg_fts, rowid, pkgname, comment,
+                         description, categories)
+                 SELECT 'delete', id, pkgname, comment, description,
+                        categories
+                   FROM remote_pkg
+                  WHERE repository_id = :repository_id",
+                &[(":repository_id", &repository_id)],
+            )?;
         }
+        tx.execute_named(
+            "DELETE FROM remote_pkg WHERE repository_id = :repository_id",
+            &[(":repository_id", &repository_id)],
+        )?;
         Ok(())
     }
 
@@ -571,37 +1089,46 @@ impl PMDB {
             ])?;
 
             let repository_id = tx.last_insert_rowid();
-            PMDB::insert_local_pkgs(&tx, repository_id, &pkgs)?;
+            PMDB::insert_local_pkgs(&tx, repository_id, pkgs)?;
         }
 
         tx.commit()
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn insert_remote_repository(
         &mut self,
         url: &str,
+        name: &str,
         prefix: &str,
         mtime: i64,
         summary_suffix: &str,
+        summary_digest: Option<&str>,
         pkgs: &[SummaryEntry],
+        progress: &mut dyn FnMut(usize),
     ) -> rusqlite::Result<()> {
+        let fts = self.fts;
         let tx = self.db.transaction()?;
 
         {
             let mut stmt = tx.prepare(
                 "INSERT INTO remote_repository
-                        (url, prefix, mtime, summary_suffix)
-                 VALUES (:url, :prefix, :mtime, :summary_suffix)",
+                        (url, name, prefix, mtime, summary_suffix,
+                         summary_digest, updated)
+                 VALUES (:url, :name, :prefix, :mtime, :summary_suffix,
+                         :summary_digest, strftime('%s', 'now'))",
             )?;
             stmt.execute_named(&[
                 (":url", &url),
+                (":name", &name),
                 (":prefix", &prefix),
                 (":mtime", &mtime),
                 (":summary_suffix", &summary_suffix),
+                (":summary_digest", &summary_digest),
             ])?;
 
             let repository_id = tx.last_insert_rowid();
-            PMDB::insert_remote_pkgs(&tx, repository_id, &pkgs)?;
+            PMDB::insert_remote_pkgs(&tx, repository_id, pkgs, fts, progress)?;
         }
 
         tx.commit()
@@ -631,7 +1158,7 @@ impl PMDB {
              * faster.
              */
             PMDB::delete_local_pkgs(&tx, repository_id)?;
-            PMDB::insert_local_pkgs(&tx, repository_id, &pkgs)?;
+            PMDB::insert_local_pkgs(&tx, repository_id, pkgs)?;
 
             let mut stmt = tx.prepare(
                 "UPDATE local_repository
@@ -649,13 +1176,18 @@ impl PMDB {
         tx.commit()
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn update_remote_repository(
         &mut self,
         url: &str,
+        name: &str,
         mtime: i64,
         summary_suffix: &str,
+        summary_digest: Option<&str>,
         pkgs: &[SummaryEntry],
+        progress: &mut dyn FnMut(usize),
     ) -> rusqlite::Result<()> {
+        let fts = self.fts;
         let tx = self.db.transaction()?;
 
         {
@@ -672,18 +1204,23 @@ impl PMDB {
              * nightmare.  Dropping and re-inserting is a lot simpler and
              * faster.
              */
-            PMDB::delete_remote_pkgs(&tx, repository_id)?;
-            PMDB::insert_remote_pkgs(&tx, repository_id, &pkgs)?;
+            PMDB::delete_remote_pkgs(&tx, repository_id, fts)?;
+            PMDB::insert_remote_pkgs(&tx, repository_id, pkgs, fts, progress)?;
 
             let mut stmt = tx.prepare(
                 "UPDATE remote_repository
-                    SET mtime = :mtime,
-                        summary_suffix = :summary_suffix
+                    SET name = :name,
+                        mtime = :mtime,
+                        summary_suffix = :summary_suffix,
+                        summary_digest = :summary_digest,
+                        updated = strftime('%s', 'now')
                   WHERE url = :url",
             )?;
             stmt.execute_named(&[
+                (":name", &name),
                 (":mtime", &mtime),
                 (":summary_suffix", &summary_suffix),
+                (":summary_digest", &summary_digest),
                 (":url", &url),
             ])?;
         }
@@ -691,6 +1228,144 @@ impl PMDB {
         tx.commit()
     }
 
+    /*
+     * Record that a repository was found to be up to date, along with a new
+     * name in case it has been renamed in the configuration.
+     */
+    pub fn touch_remote_repository(
+        &mut self,
+        url: &str,
+        name: &str,
+    ) -> rusqlite::Result<()> {
+        self.db.execute_named(
+            "UPDATE remote_repository
+                SET name = :name,
+                    updated = strftime('%s', 'now')
+              WHERE url = :url",
+            &[(":name", &name), (":url", &url)],
+        )?;
+        Ok(())
+    }
+
+    /*
+     * Remove a remote repository and all of its packages, for when it is
+     * removed from the configuration.
+     */
+    pub fn delete_remote_repository(
+        &mut self,
+        url: &str,
+    ) -> rusqlite::Result<()> {
+        let fts = self.fts;
+        let tx = self.db.transaction()?;
+        let repository_id: Option<i64> = tx
+            .query_row_named(
+                "SELECT id FROM remote_repository WHERE url = :url",
+                &[(":url", &url)],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(repository_id) = repository_id {
+            PMDB::delete_remote_pkgs(&tx, repository_id, fts)?;
+            tx.execute_named(
+                "DELETE FROM remote_repository WHERE id = :id",
+                &[(":id", &repository_id)],
+            )?;
+        }
+        tx.commit()
+    }
+
+    /*
+     * Support functions for "repo list".
+     */
+    pub fn count_remote_pkgs(
+        &self,
+        repository_id: i64,
+    ) -> rusqlite::Result<i64> {
+        self.db.query_row_named(
+            "SELECT COUNT(*)
+               FROM remote_pkg
+              WHERE repository_id = :repository_id",
+            &[(":repository_id", &repository_id)],
+            |row| row.get(0),
+        )
+    }
+
+    /*
+     * Return where the recorded pkg-vulnerabilities came from, its
+     * modification time, and its checksum.
+     */
+    pub fn get_vulnerability_source(
+        &self,
+    ) -> rusqlite::Result<Option<(String, i64, String)>> {
+        self.db
+            .query_row(
+                "SELECT location, mtime, checksum
+                   FROM vulnerability_source",
+                rusqlite::NO_PARAMS,
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()
+    }
+
+    /*
+     * Return every recorded vulnerability as (pattern, type, url).
+     */
+    pub fn get_vulnerabilities(
+        &self,
+    ) -> rusqlite::Result<Vec<(String, String, String)>> {
+        let mut stmt = self.db.prepare(
+            "SELECT pattern, type, url
+               FROM vulnerability
+           ORDER BY id",
+        )?;
+        let rows = stmt.query_map(rusqlite::NO_PARAMS, |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?;
+        rows.collect()
+    }
+
+    /*
+     * Replace the recorded pkg-vulnerabilities.
+     */
+    pub fn replace_vulnerabilities(
+        &mut self,
+        location: &str,
+        mtime: i64,
+        checksum: &str,
+        vulns: &[(String, String, String)],
+    ) -> rusqlite::Result<()> {
+        let tx = self.db.transaction()?;
+        {
+            tx.execute_batch(
+                "DELETE FROM vulnerability_source;
+                 DELETE FROM vulnerability;",
+            )?;
+            tx.execute_named(
+                "INSERT INTO vulnerability_source
+                        (location, mtime, checksum)
+                 VALUES (:location, :mtime, :checksum)",
+                &[
+                    (":location", &location),
+                    (":mtime", &mtime),
+                    (":checksum", &checksum),
+                ],
+            )?;
+            let mut stmt = tx.prepare(
+                "INSERT INTO vulnerability
+                        (pattern, type, url)
+                 VALUES (:pattern, :type, :url)",
+            )?;
+            for (pattern, vtype, url) in vulns {
+                stmt.execute_named(&[
+                    (":pattern", pattern),
+                    (":type", vtype),
+                    (":url", url),
+                ])?;
+            }
+        }
+        tx.commit()
+    }
+
     /*
      * Support functions for "avail" and "list".
      */
@@ -701,49 +1376,205 @@ impl PMDB {
         let mut result = Vec::new();
         let mut stmt = self.db.prepare(
             "
-                SELECT pkgname, comment
+                SELECT local_pkg.id, repository_id, automatic, build_date,
+                       categories, comment, description, homepage, license,
+                       opsys, os_version, pkg_options, pkgbase, pkgname,
+                       pkgpath, pkgtools_version, pkgversion, size_pkg
                   FROM local_pkg
             INNER JOIN local_repository
                     ON local_repository.id = local_pkg.repository_id
                  WHERE local_repository.prefix = :prefix
               ORDER BY pkgname ASC",
         )?;
-        let rows =
-            stmt.query_map_named(&[(":prefix", &prefix)], |row| Ok(PackageList {
-                comment: row.get(0)?,
-                pkgname: row.get(1)?,
+        let rows = stmt.query_map_named(&[(":prefix", &prefix)], |row| {
+            Ok(PackageList {
+                id: row.get(0)?,
+                repository_id: row.get(1)?,
+                automatic: row.get(2)?,
+                build_date: row.get(3)?,
+                categories: row.get(4)?,
+                comment: row.get(5)?,
+                description: row.get(6)?,
+                homepage: row.get::<_, Option<String>>(7)?.unwrap_or_default(),
+                license: row.get::<_, Option<String>>(8)?.unwrap_or_default(),
+                opsys: row.get(9)?,
+                os_version: row.get(10)?,
+                pkg_options: row
+                    .get::<_, Option<String>>(11)?
+                    .unwrap_or_default(),
+                pkgbase: row.get(12)?,
+                pkgname: row.get(13)?,
+                pkgpath: row.get(14)?,
+                pkgtools_version: row.get(15)?,
+                pkgversion: row.get(16)?,
+                size_pkg: row.get(17)?,
                 ..Default::default()
-            }))?;
+            })
+        })?;
         for row in rows {
             result.push(row?)
         }
         Ok(result)
     }
+
+    /*
+     * Return every package available for a prefix, along with all of their
+     * details if requested.
+     */
     pub fn get_remote_pkglist_by_prefix(
         &mut self,
         prefix: &str,
+        details: bool,
     ) -> rusqlite::Result<Vec<PackageList>> {
         let mut result = Vec::new();
-        let mut stmt = self.db.prepare(
+        let (columns, from_row): (String, RemotePkgFromRow) = if details {
+            (
+                format!(
+                    "{}, {}",
+                    REMOTE_PKG_COLUMNS, REMOTE_PKG_DETAIL_COLUMNS
+                ),
+                remote_pkg_details_from_row,
+            )
+        } else {
+            (REMOTE_PKG_COLUMNS.to_string(), remote_pkg_from_row)
+        };
+        let mut stmt = self.db.prepare(&format!(
+            "
+                SELECT {}
+                  FROM remote_pkg
+            INNER JOIN remote_repository
+                    ON remote_repository.id = remote_pkg.repository_id
+                 WHERE remote_repository.prefix = :prefix
+              ORDER BY pkgname ASC",
+            columns
+        ))?;
+        let rows = stmt.query_map_named(&[(":prefix", &prefix)], from_row)?;
+        for row in rows {
+            result.push(row?)
+        }
+        Ok(result)
+    }
+
+    /*
+     * Return every copy of a package available for a prefix, given either
+     * its PKGBASE or PKGNAME.  All versions are returned so that the copy to
+     * use can be chosen in the same way as for the full list.
+     */
+    pub fn get_remote_pkglist_by_name(
+        &mut self,
+        prefix: &str,
+        name: &str,
+    ) -> rusqlite::Result<Vec<PackageList>> {
+        let mut result = Vec::new();
+        let mut stmt = self.db.prepare(&format!(
             "
-                SELECT pkgname, comment
+                SELECT {}
                   FROM remote_pkg
             INNER JOIN remote_repository
                     ON remote_repository.id = remote_pkg.repository_id
                  WHERE remote_repository.prefix = :prefix
+                   AND (pkgbase = :name
+                        OR pkgbase IN (SELECT pkgbase
+                                         FROM remote_pkg
+                                        WHERE pkgname = :name))
               ORDER BY pkgname ASC",
+            REMOTE_PKG_COLUMNS
+        ))?;
+        let rows = stmt.query_map_named(
+            &[(":prefix", &prefix), (":name", &name)],
+            remote_pkg_from_row,
+        )?;
+        for row in rows {
+            result.push(row?)
+        }
+        Ok(result)
+    }
+
+    /*
+     * Support function for "search --text".  Return the ids of packages for
+     * a prefix matching a full-text query, best match first.  Matches in
+     * PKGNAME count for the most, followed by COMMENT, CATEGORIES, and then
+     * DESCRIPTION.
+     */
+    pub fn search_remote_pkgs(
+        &self,
+        prefix: &str,
+        query: &str,
+    ) -> rusqlite::Result<Vec<i64>> {
+        let mut result = Vec::new();
+        let mut stmt = self.db.prepare(
+            "
+                SELECT remote_pkg_fts.rowid
+                  FROM remote_pkg_fts
+            INNER JOIN remote_pkg
+                    ON remote_pkg.id = remote_pkg_fts.rowid
+            INNER JOIN remote_repository
+                    ON remote_repository.id = remote_pkg.repository_id
+                 WHERE remote_pkg_fts MATCH :query
+                   AND remote_repository.prefix = :prefix
+              ORDER BY bm25(remote_pkg_fts, 10.0, 5.0, 1.0, 2.0)",
+        )?;
+        let rows = stmt.query_map_named(
+            &[(":query", &query), (":prefix", &prefix)],
+            |row| row.get(0),
         )?;
-        let rows =
-            stmt.query_map_named(&[(":prefix", &prefix)], |row| Ok(PackageList {
-                pkgname: row.get(0)?,
-                comment: row.get(1)?,
-                ..Default::default()
-            }))?;
         for row in rows {
             result.push(row?)
         }
         Ok(result)
     }
+
+    /*
+     * Return the packages currently recorded for a remote repository, so
+     * that they can be compared against a new pkg_summary.
+     */
+    pub fn get_remote_pkglist_by_repository(
+        &self,
+        repository_id: i64,
+    ) -> rusqlite::Result<Vec<PackageList>> {
+        let mut result = Vec::new();
+        let mut stmt = self.db.prepare(
+            "SELECT pkgname, pkgbase, pkgversion, build_date
+               FROM remote_pkg
+              WHERE repository_id = :repository_id",
+        )?;
+        let rows = stmt.query_map_named(
+            &[(":repository_id", &repository_id)],
+            |row| {
+                Ok(PackageList {
+                    pkgname: row.get(0)?,
+                    pkgbase: row.get(1)?,
+                    pkgversion: row.get(2)?,
+                    build_date: row.get(3)?,
+                    ..Default::default()
+                })
+            },
+        )?;
+        for row in rows {
+            result.push(row?)
+        }
+        Ok(result)
+    }
+
+    /*
+     * Support functions for "info".  Return everything we know about a
+     * remote package.
+     */
+    pub fn get_remote_pkg(&self, id: i64) -> rusqlite::Result<PackageList> {
+        self.db.query_row_named(
+            &format!(
+                "
+                SELECT {}, {}
+                  FROM remote_pkg
+            INNER JOIN remote_repository
+                    ON remote_repository.id = remote_pkg.repository_id
+                 WHERE remote_pkg.id = :id",
+                REMOTE_PKG_COLUMNS, REMOTE_PKG_DETAIL_COLUMNS
+            ),
+            &[(":id", &id)],
+            remote_pkg_details_from_row,
+        )
+    }
 }
 
 impl LocalRepository {
@@ -753,7 +1584,43 @@ impl LocalRepository {
 }
 
 impl RemoteRepository {
-    pub fn up_to_date(&self, mtime: i64, summary_suffix: &str) -> bool {
-        self.mtime == mtime && self.summary_suffix == summary_suffix
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn mtime(&self) -> i64 {
+        self.mtime
+    }
+
+    pub fn summary_suffix(&self) -> &str {
+        &self.summary_suffix
+    }
+
+    pub fn updated(&self) -> Option<i64> {
+        self.updated
+    }
+
+    /*
+     * If the repository publishes a digest of its pkg_summary then that is
+     * used to tell whether it has changed, as it is more reliable than the
+     * Last-Modified time.
+     */
+    pub fn up_to_date(
+        &self,
+        mtime: i64,
+        summary_suffix: &str,
+        summary_digest: Option<&str>,
+    ) -> bool {
+        if self.summary_suffix != summary_suffix {
+            return false;
+        }
+        match summary_digest {
+            Some(digest) => self.summary_digest.as_deref() == Some(digest),
+            None => self.mtime == mtime,
+        }
     }
 }
@@ -0,0 +1,147 @@
+/*
+ * Copyright (c) 2019 Jonathan Perkin <jonathan@perkin.org.uk>
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ *
+ * progress.rs - progress reporting for long running operations.
+ */
+
+use std::io::{IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+/*
+ * How often to redraw the progress line on a terminal, and how often to
+ * print a new progress line otherwise.
+ */
+const TTY_INTERVAL: Duration = Duration::from_millis(100);
+const LINE_INTERVAL: Duration = Duration::from_secs(5);
+
+/*
+ * Progress for a single repository, written to stderr.  On a terminal a
+ * single line is continuously redrawn, otherwise plain lines are printed
+ * occasionally so that logs are not flooded.
+ */
+pub struct Progress {
+    label: String,
+    tty: bool,
+    start: Instant,
+    last: Instant,
+}
+
+fn format_bytes(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, units[unit])
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+impl Progress {
+    pub fn new(label: &str) -> Progress {
+        Progress {
+            label: label.to_string(),
+            tty: std::io::stderr().is_terminal(),
+            start: Instant::now(),
+            last: Instant::now(),
+        }
+    }
+
+    /*
+     * Report progress, unless we reported (or finished a stage) too
+     * recently.  Completion of each stage is always reported using done().
+     */
+    fn report(&mut self, msg: &str) {
+        let now = Instant::now();
+        let interval = if self.tty {
+            TTY_INTERVAL
+        } else {
+            LINE_INTERVAL
+        };
+        if now.duration_since(self.last) < interval {
+            return;
+        }
+        self.last = now;
+        if self.tty {
+            eprint!("\r\x1b[K{}: {}", self.label, msg);
+            let _ = std::io::stderr().flush();
+        } else {
+            eprintln!("{}: {}", self.label, msg);
+        }
+    }
+
+    /*
+     * Report the end of a stage.  This is always printed, replacing any
+     * progress line on a terminal.
+     */
+    fn done(&mut self, msg: &str) {
+        if self.tty {
+            eprintln!("\r\x1b[K{}: {}", self.label, msg);
+        } else {
+            eprintln!("{}: {}", self.label, msg);
+        }
+        self.last = Instant::now();
+    }
+
+    pub fn download(&mut self, bytes: u64, total: Option<u64>) {
+        let msg = match total {
+            Some(total) if total > 0 => format!(
+                "downloading {} / {} ({}%)",
+                format_bytes(bytes),
+                format_bytes(total),
+                bytes * 100 / total
+            ),
+            _ => format!("downloading {}", format_bytes(bytes)),
+        };
+        self.report(&msg);
+    }
+
+    pub fn download_done(&mut self, bytes: u64) {
+        let msg = format!(
+            "downloaded {} in {:.1}s",
+            format_bytes(bytes),
+            self.start.elapsed().as_secs_f64()
+        );
+        self.done(&msg);
+    }
+
+    pub fn parse(&mut self, entries: usize) {
+        self.report(&format!("parsing, {} entries", entries));
+    }
+
+    pub fn parse_done(&mut self, entries: usize) {
+        self.done(&format!("parsed {} entries", entries));
+    }
+
+    pub fn record(&mut self, done: usize, total: usize) {
+        self.report(&format!("recording packages, {}/{}", done, total));
+    }
+
+    /*
+     * The repository is complete, report how long it took overall.
+     */
+    pub fn finish(&mut self, total: usize) {
+        let msg = format!(
+            "recorded {} packages, {:.1}s elapsed",
+            total,
+            self.start.elapsed().as_secs_f64()
+        );
+        self.done(&msg);
+    }
+}
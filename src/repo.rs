@@ -0,0 +1,329 @@
+/*
+ * Copyright (c) 2019 Jonathan Perkin <jonathan@perkin.org.uk>
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ *
+ * repo.rs - handle "pm repo" commands.
+ */
+
+extern crate toml_edit;
+
+use crate::config;
+use crate::pmdb::PMDB;
+use chrono::{Local, TimeZone};
+use std::fs;
+use toml_edit::{value, ArrayOfTables, Decor, DocumentMut, Item, Table};
+
+/*
+ * Find the repository a user is referring to, either by name or by URL, and
+ * return its prefix and position within that prefix.  If a prefix was chosen
+ * with -p then only that prefix is searched, which is the only way to tell
+ * apart the same repository configured for different prefixes.
+ */
+fn find<'a>(
+    cfg: &'a config::Config,
+    target: &str,
+    explicit_prefix: bool,
+) -> (&'a config::Prefix, usize) {
+    let target_url = target.trim_end_matches('/');
+    let mut found = vec![];
+    for prefix in cfg.prefixes() {
+        if explicit_prefix && prefix.path() != cfg.prefix() {
+            continue;
+        }
+        for (i, repo) in prefix.repositories().iter().flatten().enumerate() {
+            if repo.name().as_deref() == Some(target)
+                || repo.url().trim_end_matches('/') == target_url
+            {
+                found.push((prefix, i));
+            }
+        }
+    }
+    match found.len() {
+        0 => {
+            eprintln!("ERROR: No repository matches \"{}\"", target);
+            std::process::exit(1);
+        }
+        1 => found.remove(0),
+        _ => {
+            eprintln!(
+                "ERROR: \"{}\" matches more than one repository, use -p to \
+                 choose",
+                target
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+fn load_document(
+    cfg: &config::Config,
+) -> Result<DocumentMut, Box<dyn std::error::Error>> {
+    let contents = fs::read_to_string(cfg.filename())?;
+    Ok(contents
+        .parse::<DocumentMut>()
+        .map_err(|e| format!("{}: {}", cfg.filename().display(), e))?)
+}
+
+fn save_document(
+    cfg: &config::Config,
+    doc: &DocumentMut,
+) -> Result<(), Box<dyn std::error::Error>> {
+    fs::write(cfg.filename(), doc.to_string())
+        .map_err(|e| format!("{}: {}", cfg.filename().display(), e).into())
+}
+
+/*
+ * Return the [[prefix]] table for a prefix path in the configuration file.
+ */
+fn prefix_table<'a>(
+    doc: &'a mut DocumentMut,
+    path: &str,
+) -> Option<&'a mut Table> {
+    doc.get_mut("prefix")?
+        .as_array_of_tables_mut()?
+        .iter_mut()
+        .find(|t| t.get("path").and_then(Item::as_str) == Some(path))
+}
+
+/*
+ * Return the [[prefix.repository]] table at a position within a prefix.
+ * Repositories are loaded in the order they appear in the file, so this is
+ * the same position as returned by find().
+ */
+fn repository_table<'a>(
+    doc: &'a mut DocumentMut,
+    path: &str,
+    index: usize,
+) -> Result<&'a mut Table, Box<dyn std::error::Error>> {
+    prefix_table(doc, path)
+        .and_then(|t| t.get_mut("repository"))
+        .and_then(Item::as_array_of_tables_mut)
+        .and_then(|repos| repos.get_mut(index))
+        .ok_or_else(|| "Configuration file changed while loading".into())
+}
+
+pub fn list(
+    cfg: &config::Config,
+    db: &mut PMDB,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut rows = vec![];
+    for prefix in cfg.prefixes() {
+        for repo in prefix.repositories().iter().flatten() {
+            let (pkgs, suffix, updated) =
+                match db.get_remote_repository(repo.url())? {
+                    Some(r) => (
+                        db.count_remote_pkgs(r.id())?.to_string(),
+                        r.summary_suffix().to_string(),
+                        match r.updated() {
+                            Some(t) => Local
+                                .timestamp(t, 0)
+                                .format("%Y-%m-%d %H:%M")
+                                .to_string(),
+                            None => "-".into(),
+                        },
+                    ),
+                    None => ("-".into(), "-".into(), "never".into()),
+                };
+            let state = if repo.enabled() {
+                "enabled"
+            } else {
+                "disabled"
+            };
+            rows.push([
+                repo.name().clone().unwrap_or_else(|| "-".into()),
+                prefix.path().to_string(),
+                repo.url().to_string(),
+                pkgs,
+                suffix,
+                updated,
+                state.to_string(),
+            ]);
+        }
+    }
+    if rows.is_empty() {
+        eprintln!("No repositories configured");
+        std::process::exit(1);
+    }
+
+    let header = [
+        "NAME", "PREFIX", "URL", "PACKAGES", "SUFFIX", "UPDATED", "STATE",
+    ];
+    let mut widths: Vec<usize> = header.iter().map(|h| h.len()).collect();
+    for row in &rows {
+        for (w, col) in widths.iter_mut().zip(row.iter()) {
+            *w = (*w).max(col.len());
+        }
+    }
+    let print = |cols: Vec<&str>| {
+        let line: Vec<String> = cols
+            .iter()
+            .zip(&widths)
+            .map(|(c, w)| format!("{:w$}", c, w = w))
+            .collect();
+        println!("{}", line.join(" ").trim_end());
+    };
+    print(header.to_vec());
+    for row in &rows {
+        print(row.iter().map(String::as_str).collect());
+    }
+    Ok(())
+}
+
+/*
+ * Add a repository to the default prefix.
+ */
+pub fn add(
+    cfg: &config::Config,
+    url: &str,
+    name: &Option<String>,
+    priority: Option<i64>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let display_url = config::strip_userinfo(url);
+    if let Some(prefix) = cfg.get_prefix(cfg.prefix()) {
+        for repo in prefix.repositories().iter().flatten() {
+            if repo.url().trim_end_matches('/')
+                == display_url.trim_end_matches('/')
+            {
+                eprintln!(
+                    "ERROR: {} is already configured under {}",
+                    display_url,
+                    prefix.path()
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(name) = name {
+        if cfg
+            .prefixes()
+            .iter()
+            .flat_map(|p| p.repositories().iter().flatten())
+            .any(|r| r.name().as_ref() == Some(name))
+        {
+            eprintln!("ERROR: A repository named {} already exists", name);
+            std::process::exit(1);
+        }
+    }
+
+    let mut doc = load_document(cfg)?;
+    let prefix = match prefix_table(&mut doc, cfg.prefix()) {
+        Some(prefix) => prefix,
+        None => {
+            eprintln!(
+                "ERROR: Prefix {} is not configured in {}",
+                cfg.prefix(),
+                cfg.filename().display()
+            );
+            std::process::exit(1);
+        }
+    };
+    let repos = prefix
+        .entry("repository")
+        .or_insert_with(|| Item::ArrayOfTables(ArrayOfTables::new()))
+        .as_array_of_tables_mut()
+        .ok_or("\"repository\" is not an array of tables")?;
+
+    let mut repo = Table::new();
+    if let Some(name) = name {
+        repo["name"] = value(name.as_str());
+    }
+    repo["url"] = value(url);
+    if let Some(priority) = priority {
+        repo["priority"] = value(priority);
+    }
+
+    /*
+     * Indent the new table and its keys in the same way as the last
+     * repository, so that it fits in with the rest of the file.
+     */
+    if let Some(last) = repos.iter().last() {
+        let indent = |decor: &Decor| {
+            decor
+                .prefix()
+                .and_then(|p| p.as_str())
+                .and_then(|p| p.rsplit('\n').next())
+                .unwrap_or("")
+                .to_string()
+        };
+        repo.decor_mut().set_prefix(indent(last.decor()));
+        if let Some(key) = last.iter().next().and_then(|(k, _)| last.key(k)) {
+            let prefix = indent(key.leaf_decor());
+            for (mut key, _) in repo.iter_mut() {
+                key.leaf_decor_mut().set_prefix(prefix.clone());
+            }
+        }
+    }
+    repos.push(repo);
+    save_document(cfg, &doc)?;
+
+    println!("Added {} to {}", display_url, cfg.prefix());
+    Ok(())
+}
+
+/*
+ * Remove a repository from the configuration, along with any packages
+ * recorded for it in the database.
+ */
+pub fn remove(
+    cfg: &config::Config,
+    db: &mut PMDB,
+    target: &str,
+    explicit_prefix: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (prefix, index) = find(cfg, target, explicit_prefix);
+    let repo = &prefix.repositories().as_ref().unwrap()[index];
+
+    let mut doc = load_document(cfg)?;
+    prefix_table(&mut doc, prefix.path())
+        .and_then(|t| t.get_mut("repository"))
+        .and_then(Item::as_array_of_tables_mut)
+        .filter(|repos| index < repos.len())
+        .ok_or("Configuration file changed while loading")?
+        .remove(index);
+    save_document(cfg, &doc)?;
+    db.delete_remote_repository(repo.url())?;
+
+    println!("Removed {} from {}", repo.label(), prefix.path());
+    Ok(())
+}
+
+/*
+ * Enable or disable a repository.  Enabling removes the "enabled" key
+ * rather than setting it, as that is the default.
+ */
+pub fn set_enabled(
+    cfg: &config::Config,
+    target: &str,
+    explicit_prefix: bool,
+    enabled: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (prefix, index) = find(cfg, target, explicit_prefix);
+    let repo = &prefix.repositories().as_ref().unwrap()[index];
+
+    let mut doc = load_document(cfg)?;
+    let table = repository_table(&mut doc, prefix.path(), index)?;
+    if enabled {
+        table.remove("enabled");
+    } else {
+        table["enabled"] = value(false);
+    }
+    save_document(cfg, &doc)?;
+
+    println!(
+        "{} {}",
+        if enabled { "Enabled" } else { "Disabled" },
+        repo.label()
+    );
+    Ok(())
+}
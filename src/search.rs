@@ -14,32 +14,376 @@
  * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
  *
  * search.rs - handle "pm search" command.
+ *
+ * By default the query is a regular expression matched against PKGNAME.
+ * Field options match a pattern against other fields instead, and every
+ * pattern given must match for a package to be shown.  Patterns are
+ * regular expressions unless another mode is chosen, and are matched
+ * case-insensitively (to avoid the "ImageMagick" problem) unless asked not
+ * to.
  */
 
+use crate::audit::Vulnerabilities;
 use crate::config;
+use crate::dewey;
+use crate::list::{self, PackageList};
+use crate::output;
 use crate::pmdb::PMDB;
-use regex::Regex;
+use crate::select;
+use regex::{Regex, RegexBuilder};
+use std::collections::HashMap;
+
+/*
+ * How patterns are matched.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Mode {
+    /* Regular expression matching anywhere in a field. */
+    #[default]
+    Regex,
+    /* The whole field, or PKGBASE as well as PKGNAME for the query. */
+    Exact,
+    /* pkgsrc patterns for the query, globs for anything else. */
+    Glob,
+    /* Within a few edits of PKGBASE, or any word of other fields. */
+    Fuzzy,
+}
+
+/*
+ * Options for "pm search".
+ */
+#[derive(Debug, Default)]
+pub struct Options {
+    /* Pattern matched against PKGNAME, or text for --text. */
+    pub query: Option<String>,
+    /* Treat the query as words for a full-text search. */
+    pub text: bool,
+    /* Patterns matched against individual fields. */
+    pub comment: Option<String>,
+    pub description: Option<String>,
+    pub category: Option<String>,
+    pub pkgpath: Option<String>,
+    pub homepage: Option<String>,
+    pub license: Option<String>,
+    /* Pattern matched against any of the above, or PKGNAME. */
+    pub all: Option<String>,
+    /* How patterns are matched. */
+    pub mode: Mode,
+    /* Match case exactly. */
+    pub case_sensitive: bool,
+    /* Hide packages with known vulnerabilities. */
+    pub no_vulnerable: bool,
+    /* Hide packages whose licenses are not acceptable. */
+    pub acceptable: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Field {
+    Pkgname,
+    Comment,
+    Description,
+    Category,
+    Pkgpath,
+    Homepage,
+    License,
+}
+
+const ALL_FIELDS: &[Field] = &[
+    Field::Pkgname,
+    Field::Comment,
+    Field::Description,
+    Field::Category,
+    Field::Pkgpath,
+    Field::Homepage,
+    Field::License,
+];
+
+/*
+ * Return the strings of a field that a pattern is tried against.  Each
+ * category is matched on its own, so that "^databases$" finds packages in
+ * exactly that category.
+ */
+fn candidates(field: Field, mode: Mode, pkg: &PackageList) -> Vec<&str> {
+    let text = match field {
+        Field::Pkgname => {
+            return match mode {
+                Mode::Exact => vec![pkg.pkgname(), pkg.pkgbase()],
+                Mode::Fuzzy => vec![pkg.pkgbase()],
+                _ => vec![pkg.pkgname()],
+            };
+        }
+        Field::Category => {
+            return pkg.categories().split_whitespace().collect()
+        }
+        Field::Comment => pkg.comment(),
+        Field::Description => pkg.description(),
+        Field::Pkgpath => pkg.pkgpath(),
+        Field::Homepage => pkg.homepage(),
+        Field::License => pkg.license(),
+    };
+    match mode {
+        Mode::Fuzzy => text.split_whitespace().collect(),
+        _ => vec![text],
+    }
+}
+
+/*
+ * Return the number of single character insertions, deletions, or
+ * substitutions needed to turn one string into another.
+ */
+fn edit_distance(a: &[char], b: &str) -> usize {
+    let mut prev: Vec<usize> = (0..=a.len()).collect();
+    let mut cur = vec![0; a.len() + 1];
+    for (j, cb) in b.chars().enumerate() {
+        cur[0] = j + 1;
+        for (i, ca) in a.iter().enumerate() {
+            let cost = if *ca == cb { 0 } else { 1 };
+            cur[i + 1] = (prev[i] + cost).min(prev[i + 1] + 1).min(cur[i] + 1);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    prev[a.len()]
+}
+
+/*
+ * A compiled pattern.  Apart from regular expressions, which handle case
+ * themselves, patterns are stored lowercase when matching without case.
+ */
+enum Pattern {
+    Regex(Regex),
+    Exact(String),
+    Glob(String, glob::Pattern),
+    Fuzzy(Vec<char>),
+}
+
+impl Pattern {
+    fn new(
+        pattern: &str,
+        mode: Mode,
+        case_sensitive: bool,
+    ) -> Result<Pattern, String> {
+        if mode == Mode::Regex {
+            return RegexBuilder::new(pattern)
+                .case_insensitive(!case_sensitive)
+                .build()
+                .map(Pattern::Regex)
+                .map_err(|e| {
+                    format!("Invalid regular expression \"{}\": {}", pattern, e)
+                });
+        }
+        let pattern = if case_sensitive {
+            pattern.to_string()
+        } else {
+            pattern.to_lowercase()
+        };
+        Ok(match mode {
+            Mode::Exact => Pattern::Exact(pattern),
+            Mode::Glob => {
+                let glob = glob::Pattern::new(&pattern).map_err(|e| {
+                    format!("Invalid pattern \"{}\": {}", pattern, e)
+                })?;
+                Pattern::Glob(pattern, glob)
+            }
+            _ => Pattern::Fuzzy(pattern.chars().collect()),
+        })
+    }
+
+    /*
+     * Return how far text is from the pattern, or None if it doesn't match.
+     * Only fuzzy matches are ever any distance away.
+     */
+    fn distance(&self, text: &str, field: Field) -> Option<usize> {
+        let matched = match self {
+            Pattern::Regex(re) => re.is_match(text),
+            Pattern::Exact(pattern) => pattern == text,
+            Pattern::Glob(pattern, _) if field == Field::Pkgname => {
+                dewey::pkg_match(pattern, text)
+            }
+            Pattern::Glob(_, glob) => glob.matches(text),
+            Pattern::Fuzzy(pattern) => {
+                let distance = edit_distance(pattern, text);
+                return if distance <= (pattern.len() / 3).max(1) {
+                    Some(distance)
+                } else {
+                    None
+                };
+            }
+        };
+        if matched {
+            Some(0)
+        } else {
+            None
+        }
+    }
+}
+
+/*
+ * Every pattern to match, each along with the fields it may match.
+ */
+struct Matcher {
+    mode: Mode,
+    fold: bool,
+    terms: Vec<(&'static [Field], Pattern)>,
+}
+
+impl Matcher {
+    fn new(opts: &Options) -> Result<Matcher, String> {
+        let fields: [(&'static [Field], &Option<String>); 7] = [
+            (&[Field::Comment], &opts.comment),
+            (&[Field::Description], &opts.description),
+            (&[Field::Category], &opts.category),
+            (&[Field::Pkgpath], &opts.pkgpath),
+            (&[Field::Homepage], &opts.homepage),
+            (&[Field::License], &opts.license),
+            (ALL_FIELDS, &opts.all),
+        ];
+        let compile = |p| Pattern::new(p, opts.mode, opts.case_sensitive);
+        let mut terms = vec![];
+        if !opts.text {
+            if let Some(query) = &opts.query {
+                terms.push((&[Field::Pkgname][..], compile(query)?));
+            }
+        }
+        for (fields, pattern) in fields.iter() {
+            if let Some(pattern) = pattern {
+                terms.push((*fields, compile(pattern)?));
+            }
+        }
+        Ok(Matcher {
+            mode: opts.mode,
+            fold: !opts.case_sensitive && opts.mode != Mode::Regex,
+            terms,
+        })
+    }
+
+    /*
+     * Whether any term matches a field that is only loaded along with the
+     * details of each package.
+     */
+    fn details(&self) -> bool {
+        self.terms.iter().any(|(fields, _)| {
+            fields.iter().any(|f| {
+                !(*f == Field::Pkgname
+                    || *f == Field::Comment
+                    || *f == Field::License)
+            })
+        })
+    }
+
+    /*
+     * Return how far a package is from matching every term, or None if it
+     * doesn't.  Each term counts for its closest field.
+     */
+    fn distance(&self, pkg: &PackageList) -> Option<usize> {
+        let mut total = 0;
+        for (fields, pattern) in &self.terms {
+            total += fields
+                .iter()
+                .flat_map(|f| {
+                    candidates(*f, self.mode, pkg).into_iter().map(move |c| {
+                        if self.fold {
+                            pattern.distance(&c.to_lowercase(), *f)
+                        } else {
+                            pattern.distance(c, *f)
+                        }
+                    })
+                })
+                .flatten()
+                .min()?;
+        }
+        Some(total)
+    }
+}
+
+/*
+ * Turn search text into an FTS5 query matching packages that contain every
+ * word.  Each word is quoted so that punctuation such as "c++" or "-" is
+ * not taken as query syntax.
+ */
+fn fts_query(text: &str) -> Option<String> {
+    let words: Vec<String> = text
+        .split_whitespace()
+        .map(|w| format!("\"{}\"", w.replace('"', "\"\"")))
+        .collect();
+    if words.is_empty() {
+        None
+    } else {
+        Some(words.join(" "))
+    }
+}
+
+/*
+ * Full-text search, returning matches in order of relevance.
+ */
+fn search_text(
+    cfg: &config::Config,
+    db: &mut PMDB,
+    pkgs: Vec<PackageList>,
+    text: &str,
+) -> Result<Vec<PackageList>, Box<dyn std::error::Error>> {
+    if !db.fts() {
+        eprintln!(
+            "ERROR: --text needs SQLite built with FTS5, which this one is not"
+        );
+        std::process::exit(1);
+    }
+    let query = match fts_query(text) {
+        Some(query) => query,
+        None => {
+            eprintln!("ERROR: No search text given");
+            std::process::exit(1);
+        }
+    };
+    let mut pkgs: HashMap<i64, PackageList> =
+        pkgs.into_iter().map(|pkg| (*pkg.id(), pkg)).collect();
+    Ok(db
+        .search_remote_pkgs(cfg.prefix(), &query)?
+        .iter()
+        .filter_map(|id| pkgs.remove(id))
+        .collect())
+}
 
 pub fn run(
     cfg: &config::Config,
     db: &mut PMDB,
-    regstr: &str,
-) -> Result<(), Box<std::error::Error>> {
-    /*
-     * For now we force case-insensitive matches by default (to avoid the
-     * "ImageMagick" problem), but this should really go into search flags.
-     */
-    let refmt = format!("(?i){}", regstr);
-    let re = Regex::new(&refmt).unwrap();
-    let pkgs = db.get_remote_pkglist_by_prefix(cfg.prefix())?;
+    opts: &Options,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let matcher = match Matcher::new(opts) {
+        Ok(matcher) => matcher,
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if matcher.terms.is_empty() && !opts.text {
+        eprintln!("ERROR: No search query given");
+        std::process::exit(1);
+    }
+    let details = matcher.details() || output::wanted(cfg);
+    let pkgs = select::remote_pkglist(cfg, db, details)?;
     if pkgs.is_empty() {
         eprintln!("No packages available for prefix={}", cfg.prefix());
         std::process::exit(1);
     }
-    for pkg in pkgs {
-        if re.is_match(pkg.pkgname()) {
-            println!("{:20} {}", pkg.pkgname(), pkg.comment());
-        }
+    let pkgs = if opts.text {
+        let text = opts.query.as_deref().unwrap_or("");
+        search_text(cfg, db, pkgs, text)?
+    } else {
+        pkgs
+    };
+    let mut matches: Vec<(usize, PackageList)> = pkgs
+        .into_iter()
+        .filter_map(|pkg| matcher.distance(&pkg).map(|d| (d, pkg)))
+        .collect();
+
+    /* Closest first, otherwise keep the existing order */
+    if opts.mode == Mode::Fuzzy {
+        matches.sort_by_key(|(d, _)| *d);
     }
-    Ok(())
+    let matches: Vec<PackageList> =
+        matches.into_iter().map(|(_, pkg)| pkg).collect();
+    let vulns = Vulnerabilities::load(db)?;
+    let matches = list::filter_vulnerable(matches, &vulns, opts.no_vulnerable);
+    let matches = list::filter_licenses(cfg, matches, opts.acceptable);
+    list::print_remote(cfg, db, &matches, &vulns)
 }
@@ -0,0 +1,249 @@
+/*
+ * Copyright (c) 2019 Jonathan Perkin <jonathan@perkin.org.uk>
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ *
+ * select.rs - choose between copies of a package available from several
+ * repositories, according to repository priorities and package pins.
+ */
+
+use crate::config;
+use crate::dewey;
+use crate::list::PackageList;
+use crate::pmdb::PMDB;
+use glob::Pattern;
+use pkgsrc::pkg_match;
+use std::collections::HashMap;
+
+/*
+ * What we know about the repository a package came from.  Repositories are
+ * ranked by priority, and then by the order they are configured in, so that
+ * the first of several equal priority repositories wins.
+ */
+struct RepoInfo {
+    name: Option<String>,
+    rank: (i64, i64),
+}
+
+/*
+ * Return whether a pin applies to a PKGBASE.
+ */
+fn pin_applies(pin: &config::Pin, pkgbase: &str) -> bool {
+    match Pattern::new(pin.pkg()) {
+        Ok(p) => p.matches(pkgbase),
+        Err(_) => pin.pkg() == pkgbase,
+    }
+}
+
+/*
+ * Return whether this copy of a package satisfies a pin that applies to it.
+ */
+fn pin_satisfied(
+    pin: &config::Pin,
+    pkg: &PackageList,
+    repo: &RepoInfo,
+) -> bool {
+    if repo.name.as_deref() != Some(pin.repository()) {
+        return false;
+    }
+    match pin.version() {
+        /*
+         * Version patterns are pkgsrc patterns without the PKGBASE, so
+         * either a dewey expression such as ">=1.1<1.2", or a glob or
+         * exact version such as "1.1.*".
+         */
+        Some(version) => {
+            if version.starts_with('<') || version.starts_with('>') {
+                dewey::matches(version, pkg.pkgversion())
+            } else {
+                pkg_match(
+                    &format!("{}-{}", pkg.pkgbase(), version),
+                    pkg.pkgname(),
+                )
+            }
+        }
+        None => true,
+    }
+}
+
+/*
+ * Given every copy of every package available for a prefix, return only
+ * those that should be offered to the user.
+ *
+ * Packages from repositories that are disabled or no longer configured are
+ * dropped.  Then for each PKGBASE, if a pin matches, only copies from the
+ * pinned repository (and matching any version pattern) are kept, even if
+ * that means there are none.  Otherwise all copies from the best ranked
+ * repository that provides the package are kept.  Input order is preserved.
+ */
+fn select(
+    cfg: &config::Config,
+    db: &PMDB,
+    prefix: &str,
+    pkgs: Vec<PackageList>,
+) -> rusqlite::Result<Vec<PackageList>> {
+    /*
+     * Only repositories that are configured and enabled are considered,
+     * anything else left in the database is ignored.
+     */
+    let configured: Vec<&config::Repository> = match cfg.get_prefix(prefix) {
+        Some(p) => p.enabled_repositories().collect(),
+        None => vec![],
+    };
+    let mut repos: HashMap<i64, RepoInfo> = HashMap::new();
+    for r in db.get_remote_repositories_by_prefix(prefix)? {
+        if let Some((i, c)) = configured
+            .iter()
+            .enumerate()
+            .find(|(_, c)| c.url() == r.url())
+        {
+            repos.insert(
+                r.id(),
+                RepoInfo {
+                    name: c.name().clone(),
+                    rank: (c.priority(), -(i as i64)),
+                },
+            );
+        }
+    }
+    let pkgs: Vec<PackageList> = pkgs
+        .into_iter()
+        .filter(|pkg| repos.contains_key(pkg.repository_id()))
+        .collect();
+    let rank = |pkg: &PackageList| repos[pkg.repository_id()].rank;
+
+    /*
+     * Find the best ranked repository available for each PKGBASE.
+     */
+    let mut best: HashMap<&str, (i64, i64)> = HashMap::new();
+    for pkg in &pkgs {
+        let entry = best.entry(pkg.pkgbase().as_str()).or_insert(rank(pkg));
+        if rank(pkg) > *entry {
+            *entry = rank(pkg);
+        }
+    }
+
+    let keep: Vec<bool> = pkgs
+        .iter()
+        .map(|pkg| {
+            let repo = &repos[pkg.repository_id()];
+            if let Some(pin) =
+                cfg.pins().iter().find(|p| pin_applies(p, pkg.pkgbase()))
+            {
+                return pin_satisfied(pin, pkg, repo);
+            }
+            rank(pkg) == best[pkg.pkgbase().as_str()]
+        })
+        .collect();
+
+    Ok(pkgs
+        .into_iter()
+        .zip(keep)
+        .filter(|(_, keep)| *keep)
+        .map(|(pkg, _)| pkg)
+        .collect())
+}
+
+/*
+ * Return only the packages from a single repository, chosen by name or URL
+ * with --repo.  Priorities and pins are not applied, so that everything the
+ * repository provides is shown.
+ */
+fn only_repository(
+    cfg: &config::Config,
+    db: &PMDB,
+    prefix: &str,
+    pkgs: Vec<PackageList>,
+    target: &str,
+) -> rusqlite::Result<Vec<PackageList>> {
+    let repo = cfg.get_prefix(prefix).and_then(|p| {
+        p.enabled_repositories().find(|r| {
+            r.name().as_deref() == Some(target)
+                || r.url().trim_end_matches('/') == target.trim_end_matches('/')
+        })
+    });
+    let repo = match repo {
+        Some(repo) => repo,
+        None => {
+            eprintln!(
+                "ERROR: No enabled repository \"{}\" for prefix={}",
+                target, prefix
+            );
+            std::process::exit(1);
+        }
+    };
+    let id = db.get_remote_repository(repo.url())?.map(|r| r.id());
+    Ok(pkgs
+        .into_iter()
+        .filter(|pkg| Some(*pkg.repository_id()) == id)
+        .collect())
+}
+
+/*
+ * Return the packages available for the default prefix, with the copy to
+ * use chosen for any package provided by more than one repository.  Only
+ * what is needed to list them is loaded unless details are requested.
+ */
+pub fn remote_pkglist(
+    cfg: &config::Config,
+    db: &mut PMDB,
+    details: bool,
+) -> rusqlite::Result<Vec<PackageList>> {
+    let pkgs = db.get_remote_pkglist_by_prefix(cfg.prefix(), details)?;
+    match cfg.repo() {
+        Some(target) => only_repository(cfg, db, cfg.prefix(), pkgs, target),
+        None => select(cfg, db, cfg.prefix(), pkgs),
+    }
+}
+
+/*
+ * As remote_pkglist(), but only for packages with a PKGBASE or PKGNAME of
+ * name, which avoids loading every package.
+ */
+pub fn remote_pkglist_by_name(
+    cfg: &config::Config,
+    db: &mut PMDB,
+    name: &str,
+) -> rusqlite::Result<Vec<PackageList>> {
+    let pkgs = db.get_remote_pkglist_by_name(cfg.prefix(), name)?;
+    match cfg.repo() {
+        Some(target) => only_repository(cfg, db, cfg.prefix(), pkgs, target),
+        None => select(cfg, db, cfg.prefix(), pkgs),
+    }
+}
+
+/*
+ * Return every package available for a prefix from its enabled
+ * repositories, without choosing between copies of the same package.
+ */
+pub fn all_remote_pkglist(
+    cfg: &config::Config,
+    db: &mut PMDB,
+    prefix: &str,
+) -> rusqlite::Result<Vec<PackageList>> {
+    let configured: Vec<&config::Repository> = match cfg.get_prefix(prefix) {
+        Some(p) => p.enabled_repositories().collect(),
+        None => vec![],
+    };
+    let ids: Vec<i64> = db
+        .get_remote_repositories_by_prefix(prefix)?
+        .iter()
+        .filter(|r| configured.iter().any(|c| c.url() == r.url()))
+        .map(|r| r.id())
+        .collect();
+    Ok(db
+        .get_remote_pkglist_by_prefix(prefix, false)?
+        .into_iter()
+        .filter(|pkg| ids.contains(pkg.repository_id()))
+        .collect())
+}
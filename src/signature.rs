@@ -0,0 +1,240 @@
+/*
+ * Copyright (c) 2019 Jonathan Perkin <jonathan@perkin.org.uk>
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ *
+ * signature.rs - verify signify(1) and minisign(1) ed25519 signatures.
+ *
+ * Both tools use the same layout for keys and signatures: an optional
+ * "untrusted comment:" line followed by a base64 line containing a two byte
+ * algorithm, an eight byte key number, and then the 32 byte public key or
+ * 64 byte signature.  minisign signatures also carry a trusted comment with
+ * a second signature over it, and may sign a BLAKE2b-512 hash of the data
+ * rather than the data itself.
+ */
+
+extern crate openssl;
+
+use crate::config;
+use openssl::base64;
+use openssl::hash::{hash, MessageDigest};
+use openssl::pkey::{Id, PKey};
+use openssl::sign::Verifier;
+use std::fs;
+
+pub struct PublicKey {
+    keynum: [u8; 8],
+    key: Vec<u8>,
+}
+
+/*
+ * Decode the first base64 line that isn't a comment, checking it is the
+ * expected length and uses a supported algorithm.
+ */
+fn decode(
+    contents: &str,
+    what: &str,
+    len: usize,
+) -> Result<(Vec<u8>, bool), String> {
+    let line = contents
+        .lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty() && !l.starts_with("untrusted comment:"))
+        .ok_or_else(|| format!("empty {}", what))?;
+    let data = base64::decode_block(line)
+        .map_err(|_| format!("invalid base64 in {}", what))?;
+    if data.len() != len {
+        return Err(format!("invalid {}", what));
+    }
+    let prehashed = match &data[..2] {
+        b"Ed" => false,
+        b"ED" => true,
+        _ => return Err(format!("unsupported algorithm in {}", what)),
+    };
+    Ok((data, prehashed))
+}
+
+fn verify_ed25519(
+    key: &[u8],
+    sig: &[u8],
+    data: &[u8],
+) -> Result<bool, openssl::error::ErrorStack> {
+    let pkey = PKey::public_key_from_raw_bytes(key, Id::ED25519)?;
+    let mut verifier = Verifier::new_without_digest(&pkey)?;
+    verifier.verify_oneshot(sig, data)
+}
+
+impl PublicKey {
+    /*
+     * Parse a public key, either the contents of a .pub file or just the
+     * base64 line on its own as printed by "minisign -P".
+     */
+    pub fn parse(contents: &str) -> Result<PublicKey, String> {
+        let (data, prehashed) = decode(contents, "public key", 42)?;
+        if prehashed {
+            return Err("unsupported algorithm in public key".to_string());
+        }
+        let mut keynum = [0; 8];
+        keynum.copy_from_slice(&data[2..10]);
+        Ok(PublicKey {
+            keynum,
+            key: data[10..].to_vec(),
+        })
+    }
+
+    /*
+     * Return the public key configured for a repository, if any.
+     */
+    pub fn for_repository(
+        repo: &config::Repository,
+    ) -> Result<Option<PublicKey>, String> {
+        let contents = if let Some(key) = repo.public_key() {
+            key.clone()
+        } else if let Some(file) = repo.public_key_file() {
+            fs::read_to_string(file).map_err(|e| format!("{}: {}", file, e))?
+        } else {
+            return Ok(None);
+        };
+        PublicKey::parse(&contents).map(Some)
+    }
+
+    /*
+     * Verify a signature file over data.
+     */
+    pub fn verify(&self, signature: &[u8], data: &[u8]) -> Result<(), String> {
+        let signature = std::str::from_utf8(signature)
+            .map_err(|_| "invalid signature file".to_string())?;
+        let (sig, prehashed) = decode(signature, "signature", 74)?;
+        if sig[2..10] != self.keynum {
+            return Err("signed with a different key".to_string());
+        }
+
+        let digest;
+        let signed = if prehashed {
+            let md = MessageDigest::from_name("BLAKE2b512")
+                .ok_or("BLAKE2b-512 is not supported by OpenSSL")?;
+            digest = hash(md, data).map_err(|e| e.to_string())?;
+            &digest[..]
+        } else {
+            data
+        };
+        if !verify_ed25519(&self.key, &sig[10..], signed)
+            .map_err(|e| e.to_string())?
+        {
+            return Err("signature verification failed".to_string());
+        }
+
+        /*
+         * minisign signatures continue with a trusted comment, signed along
+         * with the signature itself.
+         */
+        let mut lines = signature
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .skip_while(|l| !l.starts_with("trusted comment:"));
+        if let Some(comment) = lines.next() {
+            let comment = &comment["trusted comment:".len()..];
+            let comment = comment.strip_prefix(' ').unwrap_or(comment);
+            let global = lines
+                .next()
+                .and_then(|l| base64::decode_block(l).ok())
+                .filter(|g| g.len() == 64)
+                .ok_or("invalid trusted comment signature")?;
+            let mut signed = sig[10..].to_vec();
+            signed.extend_from_slice(comment.as_bytes());
+            if !verify_ed25519(&self.key, &global, &signed)
+                .map_err(|e| e.to_string())?
+            {
+                return Err("trusted comment verification failed".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: &[u8] = b"PKGNAME=pm-0.1\n";
+
+    const PUBKEY: &str = "untrusted comment: signify public key
+RWQBAgMEBQYHCHhlcoMy4DpjtkQrQu+Y7rHUvv0enFAyuD1w9k5fVRpQ
+";
+
+    const OTHER_PUBKEY: &str =
+        "RWQLDA0ODxAREl/5v7c/jyyvumJt1ZvaDrSAO9Ji7fFA/27sY5c2kTIc";
+
+    const SIGNIFY: &str = "untrusted comment: verify with repo.pub
+RWQBAgMEBQYHCEMZ7Bf7LcC1/xrSjsCRW+pRG2UdG7z4p9gLukUiDlsufbKdkhnRmdju3v7hOFxquQABPykiIbDYgBEOa+rruA0=
+";
+
+    /* Signed by a different key that claims the same key number. */
+    const SIGNIFY_WRONG_KEY: &str = "untrusted comment: verify with repo.pub
+RWQBAgMEBQYHCP1xuXoKkQWelPSaYXDJAio7IL4WLE35vA6h5BUojxTwK9VybsFnlHhMTPLL43aR2haoO4+L7Nzfc75iiIJhWwM=
+";
+
+    const MINISIGN: &str = "untrusted comment: signature from minisign secret key
+RUQBAgMEBQYHCJ+YUqOph1zDr0XFy4xQZsoD4isRL/eFEwbUHjzJCgtEWYKSImXHgkaA54e14V0zR1uxkB3wc/RbRSinl5xSggw=
+trusted comment: timestamp:1565000000\tfile:pkg_summary.gz
+C+KU8Ob6WwqeKpMe8Fdbe7mHsTUqcx3Q7h4U1pM1Lk6e4bLxaFDJUSfsUVhR8Ol9G89Q4vDsoGV9co5jLkZcAw==
+";
+
+    fn verify(key: &str, sig: &str, data: &[u8]) -> Result<(), String> {
+        PublicKey::parse(key)?.verify(sig.as_bytes(), data)
+    }
+
+    #[test]
+    fn signify() {
+        assert_eq!(verify(PUBKEY, SIGNIFY, DATA), Ok(()));
+        assert_eq!(
+            verify(PUBKEY, SIGNIFY, b"PKGNAME=pm-0.2\n"),
+            Err("signature verification failed".to_string())
+        );
+    }
+
+    #[test]
+    fn minisign() {
+        assert_eq!(verify(PUBKEY, MINISIGN, DATA), Ok(()));
+        assert_eq!(
+            verify(PUBKEY, MINISIGN, b"PKGNAME=pm-0.2\n"),
+            Err("signature verification failed".to_string())
+        );
+        let tampered = MINISIGN.replace("1565000000", "1565000001");
+        assert_eq!(
+            verify(PUBKEY, &tampered, DATA),
+            Err("trusted comment verification failed".to_string())
+        );
+    }
+
+    #[test]
+    fn wrong_key() {
+        assert_eq!(
+            verify(OTHER_PUBKEY, SIGNIFY, DATA),
+            Err("signed with a different key".to_string())
+        );
+        assert_eq!(
+            verify(PUBKEY, SIGNIFY_WRONG_KEY, DATA),
+            Err("signature verification failed".to_string())
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(PublicKey::parse("").is_err());
+        assert!(PublicKey::parse("not base64!").is_err());
+        assert!(PublicKey::parse(MINISIGN.lines().nth(1).unwrap()).is_err());
+        assert!(verify(PUBKEY, "RWQBAgMEBQYHCA==", DATA).is_err());
+    }
+}
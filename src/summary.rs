@@ -0,0 +1,456 @@
+/*
+ * Copyright (c) 2019 Jonathan Perkin <jonathan@perkin.org.uk>
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ *
+ * summary.rs - parse and validate pkg_summary(5) files.
+ *
+ * SummaryStream from the pkgsrc crate panics on malformed input and prints
+ * any entries it rejects to stdout, so remote pkg_summary files are parsed
+ * here instead, keeping track of where each problem was found.
+ */
+
+use pkgsrc::SummaryEntry;
+use std::fmt;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+/*
+ * A problem found in a pkg_summary entry.  Entry and line numbers start at 1.
+ * Warnings are problems that did not cause the entry to be rejected.
+ */
+#[derive(Debug)]
+pub struct Problem {
+    entry: usize,
+    line: usize,
+    pkgname: Option<String>,
+    field: Option<String>,
+    message: String,
+    warning: bool,
+}
+
+impl Problem {
+    pub fn warning(&self) -> bool {
+        self.warning
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "entry {}", self.entry)?;
+        if let Some(pkgname) = &self.pkgname {
+            write!(f, " ({})", pkgname)?;
+        }
+        write!(f, ", line {}: ", self.line)?;
+        if let Some(field) = &self.field {
+            write!(f, "{}: ", field)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+/*
+ * A single line of the entry currently being parsed, None if it was not
+ * valid UTF-8.
+ */
+struct Line {
+    number: usize,
+    text: Option<String>,
+}
+
+/*
+ * Parse a pkg_summary incrementally, collecting valid entries and a list of
+ * problems with any that were rejected.
+ */
+#[derive(Default)]
+pub struct Parser {
+    buf: Vec<u8>,
+    lines: usize,
+    pending: Vec<Line>,
+    count: usize,
+    entries: Vec<SummaryEntry>,
+    problems: Vec<Problem>,
+}
+
+/*
+ * Parse a single KEY=VALUE line into an entry, returning the offending field
+ * (if known) and a description of the problem on failure.  Unknown fields are
+ * ignored rather than rejecting the entry, as new ones may be added to
+ * pkg_summary(5), and are returned so that they can be reported.
+ */
+fn parse_line(
+    sum: &mut SummaryEntry,
+    line: &str,
+) -> Result<Option<String>, (Option<String>, String)> {
+    let (key, value) = match line.find('=') {
+        Some(i) => (&line[..i], &line[i + 1..]),
+        None => {
+            return Err((None, format!("missing \"=\" in \"{}\"", line)));
+        }
+    };
+    let err = |msg: String| Err((Some(key.to_string()), msg));
+    match key {
+        "FILE_SIZE" | "SIZE_PKG" if value.parse::<i64>().is_err() => {
+            return err(format!("invalid size \"{}\"", value));
+        }
+        "PKGNAME" if !value.contains('-') => {
+            return err(format!("no version in \"{}\"", value));
+        }
+        _ => {}
+    }
+    if sum.parse_entry(key, value).is_err() {
+        return Ok(Some(key.to_string()));
+    }
+    Ok(None)
+}
+
+impl Parser {
+    pub fn new() -> Parser {
+        Default::default()
+    }
+
+    /*
+     * Valid entries parsed so far.
+     */
+    pub fn entries(&self) -> &Vec<SummaryEntry> {
+        &self.entries
+    }
+
+    pub fn into_entries(self) -> Vec<SummaryEntry> {
+        self.entries
+    }
+
+    /*
+     * Problems with the entries that were rejected, along with any warnings.
+     */
+    pub fn problems(&self) -> &Vec<Problem> {
+        &self.problems
+    }
+
+    /*
+     * Total number of entries seen, valid or not.
+     */
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /*
+     * Parse the entry made up of the pending lines, if any.
+     */
+    fn end_entry(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        self.count += 1;
+        let first = self.pending[0].number;
+        let mut sum = SummaryEntry::new();
+        let mut problems = vec![];
+        for line in self.pending.drain(..) {
+            let result = match &line.text {
+                Some(text) => parse_line(&mut sum, text),
+                None => Err((None, "invalid UTF-8".to_string())),
+            };
+            match result {
+                Ok(Some(field)) => problems.push((
+                    line.number,
+                    Some(field),
+                    "unknown field, ignored".to_string(),
+                    true,
+                )),
+                Ok(None) => {}
+                Err((field, message)) => {
+                    problems.push((line.number, field, message, false))
+                }
+            }
+        }
+        let mut valid = problems.iter().all(|(_, _, _, warning)| *warning);
+
+        /*
+         * Only check for missing fields if everything present was valid,
+         * otherwise an invalid field would also be reported as missing.
+         */
+        if valid {
+            if let Err(e) = sum.validate() {
+                let field = e.trim_start_matches("Missing ").to_string();
+                problems.push((
+                    first,
+                    Some(field),
+                    "missing".to_string(),
+                    false,
+                ));
+                valid = false;
+            }
+        }
+
+        let pkgname = if sum.pkgname().is_empty() {
+            None
+        } else {
+            Some(sum.pkgname().to_string())
+        };
+        for (line, field, message, warning) in problems {
+            self.problems.push(Problem {
+                entry: self.count,
+                line,
+                pkgname: pkgname.clone(),
+                field,
+                message,
+                warning,
+            });
+        }
+        if valid {
+            self.entries.push(sum);
+        }
+    }
+
+    fn add_line(&mut self, line: &[u8]) {
+        self.lines += 1;
+        if line.is_empty() {
+            self.end_entry();
+            return;
+        }
+        self.pending.push(Line {
+            number: self.lines,
+            text: std::str::from_utf8(line).ok().map(str::to_string),
+        });
+    }
+
+    /*
+     * Parse more input, which does not need to end on a line boundary.
+     */
+    fn write(&mut self, input: &[u8]) {
+        self.buf.extend_from_slice(input);
+        let mut start = 0;
+        while let Some(len) = self.buf[start..].iter().position(|&b| b == b'\n')
+        {
+            let line = self.buf[start..start + len].to_vec();
+            self.add_line(&line);
+            start += len + 1;
+        }
+        self.buf.drain(..start);
+    }
+
+    /*
+     * Parse anything left over at the end of the input.
+     */
+    fn finish(&mut self) {
+        if !self.buf.is_empty() {
+            let line = std::mem::take(&mut self.buf);
+            self.add_line(&line);
+        }
+        self.end_entry();
+    }
+
+    /*
+     * Parse all of a decompressed pkg_summary, calling progress with the
+     * number of entries seen after each chunk.
+     */
+    pub fn read(
+        &mut self,
+        input: &mut dyn Read,
+        progress: &mut dyn FnMut(usize),
+    ) -> std::io::Result<()> {
+        let mut buf = [0; 65536];
+        loop {
+            let n = input.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            self.write(&buf[..n]);
+            progress(self.count);
+        }
+        self.finish();
+        Ok(())
+    }
+}
+
+/*
+ * Return a reader that decompresses a pkg_summary according to its
+ * extension.
+ */
+pub fn decompress<'a>(
+    extension: &str,
+    input: &'a [u8],
+) -> Result<Box<dyn Read + 'a>, Box<dyn std::error::Error>> {
+    match extension {
+        "xz" => Ok(Box::new(xz2::read::XzDecoder::new(input))),
+        "bz2" => Ok(Box::new(bzip2::read::BzDecoder::new(input))),
+        "gz" => Ok(Box::new(flate2::read::GzDecoder::new(input))),
+        _ => {
+            Err(format!("Unsupported summary_extension \"{}\"", extension)
+                .into())
+        }
+    }
+}
+
+/*
+ * Check a local pkg_summary file, which may be compressed, and report every
+ * problem found.  Exits non-zero if any entries are invalid.
+ */
+pub fn lint(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let input = match fs::read(path) {
+        Ok(input) => input,
+        Err(e) => {
+            eprintln!("ERROR: {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    };
+    let mut reader: Box<dyn Read> =
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(e @ "xz") | Some(e @ "bz2") | Some(e @ "gz") => {
+                decompress(e, &input)?
+            }
+            _ => Box::new(input.as_slice()),
+        };
+    let mut parser = Parser::new();
+    parser
+        .read(&mut reader, &mut |_| {})
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    for problem in parser.problems() {
+        let level = if problem.warning() { "warning: " } else { "" };
+        println!("{}: {}{}", path.display(), level, problem);
+    }
+    let invalid = parser.count() - parser.entries().len();
+    println!(
+        "{}: {} entries, {} invalid",
+        path.display(),
+        parser.count(),
+        invalid
+    );
+    if invalid > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(pkgname: &str, size: &str, extra: &str) -> String {
+        format!(
+            concat!(
+                "BUILD_DATE=2019-08-01 00:00:00 +0000\n",
+                "CATEGORIES=pkgtools\n",
+                "COMMENT=Test package\n",
+                "DESCRIPTION=A test package.\n",
+                "DESCRIPTION=\n",
+                "MACHINE_ARCH=x86_64\n",
+                "OPSYS=NetBSD\n",
+                "OS_VERSION=9.0\n",
+                "PKGNAME={}\n",
+                "PKGPATH=pkgtools/pm\n",
+                "PKGTOOLS_VERSION=20190301\n",
+                "SIZE_PKG={}\n",
+                "{}"
+            ),
+            pkgname, size, extra
+        )
+    }
+
+    /*
+     * A pkg_summary with problems on known lines.  Each entry is 12 lines
+     * plus any extra, followed by a blank separator line.
+     */
+    fn summary() -> Vec<u8> {
+        let mut input = vec![];
+        input.extend(entry("one-1.0", "1024", "").bytes());
+        input.extend(b"\n\n");
+        input.extend(entry("two-1.0", "1024", "NEW_FIELD=x\n").bytes());
+        input.extend(b"\n");
+        input.extend(entry("three-1.0", "big", "").bytes());
+        input.extend(b"\n");
+        input.extend(entry("four-1.0", "1024", "").bytes());
+        input.extend(b"FOUR=\xff\n\n");
+        input.extend(entry("five", "1024", "").bytes());
+        input.extend(b"\nCOMMENT=Missing everything\n\n");
+        let last = entry("seven-1.0", "1024", "");
+        input.extend(last.trim_end().bytes());
+        input
+    }
+
+    fn parse(input: &[u8], chunk: usize) -> Parser {
+        let mut parser = Parser::new();
+        for c in input.chunks(chunk) {
+            parser.write(c);
+        }
+        parser.finish();
+        parser
+    }
+
+    fn pkgnames(parser: &Parser) -> Vec<String> {
+        parser
+            .entries()
+            .iter()
+            .map(|e| e.pkgname().clone())
+            .collect()
+    }
+
+    #[test]
+    fn entries() {
+        let parser = parse(&summary(), 65536);
+        assert_eq!(parser.count(), 7);
+        assert_eq!(pkgnames(&parser), ["one-1.0", "two-1.0", "seven-1.0"]);
+        assert_eq!(*parser.entries()[2].size_pkg(), Some(1024));
+    }
+
+    #[test]
+    fn problems() {
+        let parser = parse(&summary(), 65536);
+        let problems: Vec<String> =
+            parser.problems().iter().map(|p| p.to_string()).collect();
+        assert_eq!(
+            problems,
+            [
+                "entry 2 (two-1.0), line 27: NEW_FIELD: unknown field, ignored",
+                "entry 3 (three-1.0), line 40: SIZE_PKG: invalid size \"big\"",
+                "entry 4 (four-1.0), line 54: invalid UTF-8",
+                "entry 5, line 64: PKGNAME: no version in \"five\"",
+                "entry 6, line 69: BUILD_DATE: missing",
+            ]
+        );
+        let warnings: Vec<bool> =
+            parser.problems().iter().map(|p| p.warning()).collect();
+        assert_eq!(warnings, [true, false, false, false, false]);
+    }
+
+    #[test]
+    fn chunks() {
+        let input = summary();
+        let expected = parse(&input, input.len());
+        for chunk in &[1, 2, 3, 7, 13, 64, 4096] {
+            let parser = parse(&input, *chunk);
+            assert_eq!(parser.count(), expected.count());
+            assert_eq!(pkgnames(&parser), pkgnames(&expected));
+            let problems: Vec<String> =
+                parser.problems().iter().map(|p| p.to_string()).collect();
+            let want: Vec<String> =
+                expected.problems().iter().map(|p| p.to_string()).collect();
+            assert_eq!(problems, want, "chunk size {}", chunk);
+        }
+    }
+
+    #[test]
+    fn read() {
+        let input = summary();
+        let mut parser = Parser::new();
+        let mut counts = vec![];
+        parser
+            .read(&mut input.as_slice(), &mut |n| counts.push(n))
+            .unwrap();
+        assert_eq!(counts, [6]);
+        assert_eq!(parser.count(), 7);
+        assert_eq!(parser.into_entries().len(), 3);
+    }
+}
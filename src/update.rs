@@ -16,18 +16,124 @@
  * update.rs - handle "pm update" command.
  */
 
-extern crate reqwest;
-
+use crate::audit;
+use crate::changes::Changes;
 use crate::config;
+use crate::digest;
+use crate::fetch;
 use crate::pmdb::PMDB;
-use pkgsrc::SummaryStream;
+use crate::progress::Progress;
+use crate::signature::PublicKey;
+use crate::summary;
+use pkgsrc::SummaryEntry;
 use std::fs;
-use std::io::BufReader;
-use std::path::PathBuf;
-use std::process::{Command, Stdio};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 use std::str;
 use std::time::SystemTime;
 
+/*
+ * Options for "pm update".
+ */
+#[derive(Debug, Default)]
+pub struct Options {
+    /* Print changes as JSON, moving status messages to stderr. */
+    pub changes_json: bool,
+    /* Save changes as JSON to a file. */
+    pub save_changes: Option<PathBuf>,
+    /* Ignore the recorded state and refresh everything selected. */
+    pub force: bool,
+    /* Prefix paths and repository names or URLs to restrict updates to. */
+    pub targets: Vec<String>,
+}
+
+/*
+ * The prefixes and repositories selected by the targets given on the command
+ * line.  A prefix path selects the prefix's installed packages and all of
+ * its repositories, a repository name or URL selects just that repository.
+ * With no targets everything is selected.
+ */
+struct Selection {
+    all: bool,
+    prefixes: Vec<String>,
+    repositories: Vec<(String, String)>,
+}
+
+impl Selection {
+    fn new(
+        cfg: &config::Config,
+        targets: &[String],
+    ) -> Result<Selection, String> {
+        let mut sel = Selection {
+            all: targets.is_empty(),
+            prefixes: vec![],
+            repositories: vec![],
+        };
+        for target in targets {
+            let target_url = target.trim_end_matches('/');
+            let mut found = false;
+            for prefix in cfg.prefixes() {
+                if prefix.path() == target.trim_end_matches('/') {
+                    sel.prefixes.push(prefix.path().to_string());
+                    found = true;
+                }
+                for repo in prefix.repositories().iter().flatten() {
+                    if repo.name().as_deref() != Some(target)
+                        && repo.url().trim_end_matches('/') != target_url
+                    {
+                        continue;
+                    }
+                    if !repo.enabled() {
+                        return Err(format!(
+                            "Repository {} is disabled",
+                            repo.label()
+                        ));
+                    }
+                    sel.repositories
+                        .push((prefix.path().to_string(), repo.url().clone()));
+                    found = true;
+                }
+            }
+            if !found {
+                return Err(format!(
+                    "No prefix or repository matches \"{}\"",
+                    target
+                ));
+            }
+        }
+        Ok(sel)
+    }
+
+    fn local(&self, prefix: &config::Prefix) -> bool {
+        self.all || self.prefixes.iter().any(|p| p == prefix.path())
+    }
+
+    fn remote(
+        &self,
+        prefix: &config::Prefix,
+        repo: &config::Repository,
+    ) -> bool {
+        self.local(prefix)
+            || self
+                .repositories
+                .iter()
+                .any(|(p, url)| p == prefix.path() && url == repo.url())
+    }
+}
+
+/*
+ * Print a status message.  When the changes are being printed as JSON these
+ * go to stderr instead, so that stdout can be fed straight into other tools.
+ */
+fn status(opts: &Options, msg: &str) {
+    if opts.changes_json {
+        eprintln!("{}", msg);
+    } else {
+        println!("{}", msg);
+    }
+}
+
 /*
  * Return a list of pkg_summary extensions to search for in the remote
  * repository.  Use the user's chosen value if specified in the config,
@@ -43,25 +149,39 @@ fn get_summary_extensions(repo: &config::Repository) -> Vec<&str> {
 }
 
 /*
- * Get Vec of packages installed under the chosen prefix.
+ * Get Vec of packages installed under the chosen prefix.  The output of
+ * pkg_info is checked in the same way as a pkg_summary, and any invalid
+ * entries are reported and skipped.
  */
 fn get_local_packages(
     prefix: &config::Prefix,
-) -> Result<SummaryStream, Box<std::error::Error>> {
+) -> Result<Vec<SummaryEntry>, Box<dyn std::error::Error>> {
     let pinfo = Command::new(prefix.pkg_info())
-        .args(&["-X", "-a"])
-        .stdout(Stdio::piped())
-        .spawn()
-        .expect("could not spawn pkg_info");
-    let mut reader = BufReader::new(pinfo.stdout.expect("fail"));
-    let mut pinfostr = SummaryStream::new();
-    std::io::copy(&mut reader, &mut pinfostr)?;
+        .args(["-X", "-a"])
+        .output()
+        .expect("could not execute pkg_info");
+    let mut parser = summary::Parser::new();
+    parser.read(&mut pinfo.stdout.as_slice(), &mut |_| {})?;
+    for problem in parser.problems() {
+        eprintln!("WARNING: {}: {}", prefix.path(), problem);
+    }
+    let invalid = parser.count() - parser.entries().len();
+    if invalid > 0 {
+        eprintln!(
+            "WARNING: {}: skipped {} invalid installed package{}",
+            prefix.path(),
+            invalid,
+            if invalid == 1 { "" } else { "s" }
+        );
+    }
+    let mut pkgs = parser.into_entries();
+
     /*
      * Look for "automatic" packages (those that have been pulled in as a
      * dependency).  This is a bit hacky, and relies upon the fact that
      * pkg_install only uses this file for automatic=yes right now.
      */
-    for pkg in pinfostr.entries_mut() {
+    for pkg in &mut pkgs {
         let autofile =
             format!("{}/{}/+INSTALLED_INFO", prefix.pkgdb(), pkg.pkgname());
         let autofile = PathBuf::from(autofile);
@@ -69,152 +189,572 @@ fn get_local_packages(
             pkg.set_automatic();
         }
     }
-    Ok(pinfostr)
+    Ok(pkgs)
+}
+
+/*
+ * Return the last modified time of a prefix's pkgdb, which changes whenever
+ * packages are added or removed.
+ */
+fn pkgdb_mtime(
+    prefix: &config::Prefix,
+) -> Result<(i64, i32), Box<dyn std::error::Error>> {
+    let mtime = fs::metadata(prefix.pkgdb())?
+        .modified()?
+        .duration_since(SystemTime::UNIX_EPOCH)?;
+    Ok((mtime.as_secs() as i64, mtime.subsec_nanos() as i32))
 }
 
 fn update_local_repository(
+    opts: &Options,
     prefix: &config::Prefix,
     db: &mut PMDB,
-) -> Result<(), Box<std::error::Error>> {
+) -> Result<(), Box<dyn std::error::Error>> {
     /*
      * Get the last modified time of the pkgdb to see if we need to refresh
      * the local package database for this prefix.
      */
-    let pkgdb_mtime = fs::metadata(prefix.pkgdb())?
-        .modified()?
-        .duration_since(SystemTime::UNIX_EPOCH)?;
-    let pkgdb_mtime_sec = pkgdb_mtime.as_secs() as i64;
-    let pkgdb_mtime_nsec = pkgdb_mtime.subsec_nanos() as i32;
+    let (pkgdb_mtime_sec, pkgdb_mtime_nsec) = pkgdb_mtime(prefix)?;
 
     if let Some(r) = db.get_local_repository(prefix.path())? {
-        if r.up_to_date(pkgdb_mtime_sec, pkgdb_mtime_nsec) {
+        if !opts.force && r.up_to_date(pkgdb_mtime_sec, pkgdb_mtime_nsec) {
             return Ok(());
         } else {
-            println!("Refreshing packages installed under {}", prefix.path());
-            let pkgs: SummaryStream = get_local_packages(&prefix)?;
+            status(
+                opts,
+                &format!(
+                    "Refreshing packages installed under {}",
+                    prefix.path()
+                ),
+            );
+            let pkgs = get_local_packages(prefix)?;
             db.update_local_repository(
                 prefix.path(),
                 pkgdb_mtime_sec,
                 pkgdb_mtime_nsec,
-                pkgs.entries(),
+                &pkgs,
             )?;
         }
     } else {
-        println!("Recording packages installed under {}", prefix.path());
-        let pkgs: SummaryStream = get_local_packages(&prefix)?;
+        status(
+            opts,
+            &format!("Recording packages installed under {}", prefix.path()),
+        );
+        let pkgs = get_local_packages(prefix)?;
         db.insert_local_repository(
             prefix.path(),
             pkgdb_mtime_sec,
             pkgdb_mtime_nsec,
-            pkgs.entries(),
+            &pkgs,
         )?;
     }
 
     Ok(())
 }
 
+/*
+ * Decompress and parse a pkg_summary.  Problems with individual entries are
+ * reported, and either the entries are skipped or, for a strict repository,
+ * the whole pkg_summary is rejected.
+ */
+fn parse_summary(
+    repo: &config::Repository,
+    extension: &str,
+    input: &[u8],
+    progress: &mut Progress,
+) -> Result<Vec<SummaryEntry>, Box<dyn std::error::Error>> {
+    let mut parser = summary::Parser::new();
+    let mut decomp = summary::decompress(extension, input)?;
+    parser.read(&mut decomp, &mut |n| progress.parse(n))?;
+    progress.parse_done(parser.count());
+
+    for problem in parser.problems() {
+        let level = if repo.strict() && !problem.warning() {
+            "ERROR"
+        } else {
+            "WARNING"
+        };
+        eprintln!("{}: {}: {}", level, repo.label(), problem);
+    }
+    let invalid = parser.count() - parser.entries().len();
+    if invalid > 0 {
+        if repo.strict() {
+            return Err(format!(
+                "pkg_summary has {} invalid entr{}",
+                invalid,
+                if invalid == 1 { "y" } else { "ies" }
+            )
+            .into());
+        }
+        eprintln!(
+            "WARNING: {}: skipped {} invalid entr{}",
+            repo.label(),
+            invalid,
+            if invalid == 1 { "y" } else { "ies" }
+        );
+    }
+
+    Ok(parser.into_entries())
+}
+
+/*
+ * Return the URL for a file configured for a repository, which is either a
+ * full URL or a file name relative to the repository URL.
+ */
+fn file_url(repo: &config::Repository, file: &str) -> String {
+    if file.contains("://") {
+        file.to_string()
+    } else {
+        format!("{}/{}", repo.url(), file)
+    }
+}
+
+/*
+ * Return the digest published for a pkg_summary, if the repository has a
+ * digest file configured.  The digest file may itself be compressed.
+ */
+fn published_digest(
+    client: &reqwest::Client,
+    repo: &config::Repository,
+    sumurl: &str,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let file = match repo.digest() {
+        Some(file) => file,
+        None => return Ok(None),
+    };
+    let url = file_url(repo, file);
+    let res = fetch::get(client, repo, &url)?;
+    if !res.status().is_success() {
+        return Err(format!("{} returned {}", url, res.status()).into());
+    }
+    let mut contents = String::new();
+    match Path::new(file).extension().and_then(|e| e.to_str()) {
+        Some(e @ ("xz" | "bz2" | "gz")) => {
+            summary::decompress(e, res.body())?
+                .read_to_string(&mut contents)
+                .map_err(|e| format!("{}: {}", url, e))?;
+        }
+        _ => contents = String::from_utf8_lossy(res.body()).into_owned(),
+    }
+    let filename = sumurl.rsplit('/').next().unwrap_or(sumurl);
+    match digest::find(&contents, filename) {
+        Some(d) => Ok(Some(d)),
+        None => Err(format!("no digest for {} in {}", filename, url).into()),
+    }
+}
+
+/*
+ * If the repository has a public key configured, fetch the signature for a
+ * pkg_summary and verify it over the compressed data as downloaded.  A
+ * missing signature is as bad as an invalid one.
+ */
+fn verify_summary(
+    client: &reqwest::Client,
+    repo: &config::Repository,
+    sumurl: &str,
+    input: &[u8],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let key = match PublicKey::for_repository(repo)? {
+        Some(key) => key,
+        None => return Ok(()),
+    };
+    let sigurl = match repo.signature() {
+        Some(s) => file_url(repo, s),
+        None => format!("{}.sig", sumurl),
+    };
+    let res = fetch::get(client, repo, &sigurl)?;
+    if !res.status().is_success() {
+        return Err(format!(
+            "pkg_summary is not signed, {} returned {}",
+            sigurl,
+            res.status()
+        )
+        .into());
+    }
+    key.verify(res.body(), input)
+        .map_err(|e| format!("{}: {}", sigurl, e))?;
+    Ok(())
+}
+
+/*
+ * Update a remote repository, returning what changed if it was updated.
+ * Nothing is returned for a repository seen for the first time, as every
+ * package would be reported as added.
+ */
 fn update_remote_repository(
+    cfg: &config::Config,
+    opts: &Options,
     prefix: &str,
     repo: &config::Repository,
     db: &mut PMDB,
-) -> Result<(), Box<std::error::Error>> {
-    let client = reqwest::Client::new();
+) -> Result<Option<Changes>, Box<dyn std::error::Error>> {
+    let client = fetch::client(cfg, repo)?;
 
-    let summary_extensions = get_summary_extensions(&repo);
+    let summary_extensions = get_summary_extensions(repo);
+    let mut progress = Progress::new(repo.label());
+    let current = db.get_remote_repository(repo.url())?;
 
     for e in summary_extensions {
         let sumurl = format!("{}/{}.{}", repo.url(), "pkg_summary", e);
 
-        let res = reqwest::Client::get(&client, sumurl.as_str()).send()?;
+        /*
+         * Unless forced, only download the pkg_summary if it has changed
+         * since it was last recorded.  Servers that ignore If-Modified-Since
+         * send it anyway, and it is then checked as normal below.  A
+         * repository with a published digest is always downloaded, as the
+         * digest is what says whether it has changed, not Last-Modified.
+         */
+        let since = match &current {
+            Some(r)
+                if !opts.force
+                    && r.summary_suffix() == e
+                    && repo.digest().is_none() =>
+            {
+                Some(r.mtime())
+            }
+            _ => None,
+        };
+        let res = fetch::get_with_progress(
+            &client,
+            repo,
+            &sumurl,
+            since,
+            &mut progress,
+        )?;
+
+        if res.status() == reqwest::StatusCode::NOT_MODIFIED {
+            status(opts, &format!("{} is up to date", repo.label()));
+            db.touch_remote_repository(repo.url(), repo.label())?;
+            return Ok(None);
+        }
+
+        /* Credentials missing or rejected, other extensions won't help */
+        if res.status() == reqwest::StatusCode::UNAUTHORIZED {
+            return Err(format!("{} returned {}", sumurl, res.status()).into());
+        }
 
         /* Not found, try next pkg_summary extension */
         if !res.status().is_success() {
             continue;
         }
 
-        /* XXX: this seems overly verbose, no simpler way? */
-        let last_modified: i64 = if let Some(lm) =
-            res.headers().get(reqwest::header::LAST_MODIFIED)
-        {
-            httpdate::parse_http_date(lm.to_str().unwrap())?
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap()
-                .as_secs() as i64
-        } else {
-            continue;
+        let last_modified = match res.last_modified() {
+            Some(lm) => lm,
+            None => continue,
         };
 
+        /*
+         * Check the pkg_summary against any published digest before doing
+         * anything else with it.
+         */
+        let digest = published_digest(&client, repo, &sumurl)?;
+        if let Some(d) = &digest {
+            if !digest::matches(d, res.body())? {
+                return Err(format!(
+                    "{} does not match its published digest",
+                    sumurl
+                )
+                .into());
+            }
+        }
+
         /*
          * We now have a valid pkg_summary, check DB for existing entry
          * and insert/update as appropriate.
          */
-        let mut sumstr = SummaryStream::new();
-
-        if let Some(r) = db.get_remote_repository(repo.url())? {
-            if r.up_to_date(last_modified, e) {
-                println!("{} is up to date", repo.url());
+        let mut changes = None;
+        if let Some(r) = &current {
+            if !opts.force && r.up_to_date(last_modified, e, digest.as_deref())
+            {
+                status(opts, &format!("{} is up to date", repo.label()));
+                db.touch_remote_repository(repo.url(), repo.label())?;
             } else {
-                println!("Updating {}", repo.url());
-                if e == "xz" {
-                    let mut decomp = xz2::read::XzDecoder::new(res);
-                    std::io::copy(&mut decomp, &mut sumstr);
-                } else if e == "bz2" {
-                    let mut decomp = bzip2::read::BzDecoder::new(res);
-                    std::io::copy(&mut decomp, &mut sumstr);
-                } else if e == "gz" {
-                    let mut decomp = flate2::read::GzDecoder::new(res);
-                    std::io::copy(&mut decomp, &mut sumstr);
-                } else {
-                    panic!("Unsupported summary_extension");
-                }
+                status(opts, &format!("Updating {}", repo.label()));
+                verify_summary(&client, repo, &sumurl, res.body())?;
+                let pkgs = parse_summary(repo, e, res.body(), &mut progress)?;
+                let total = pkgs.len();
+                let old = db.get_remote_pkglist_by_repository(r.id())?;
+                changes = Some(Changes::new(
+                    repo.label(),
+                    repo.url(),
+                    prefix,
+                    &old,
+                    &pkgs,
+                ));
                 db.update_remote_repository(
                     repo.url(),
+                    repo.label(),
                     last_modified,
                     e,
-                    sumstr.entries(),
+                    digest.as_deref(),
+                    &pkgs,
+                    &mut |i| progress.record(i, total),
                 )?;
+                progress.finish(total);
             }
         } else {
-            println!("Creating {}", repo.url());
-            if e == "xz" {
-                let mut decomp = xz2::read::XzDecoder::new(res);
-                std::io::copy(&mut decomp, &mut sumstr);
-            } else if e == "bz2" {
-                let mut decomp = bzip2::read::BzDecoder::new(res);
-                std::io::copy(&mut decomp, &mut sumstr);
-            } else if e == "gz" {
-                let mut decomp = flate2::read::GzDecoder::new(res);
-                std::io::copy(&mut decomp, &mut sumstr);
-            } else {
-                panic!("Unsupported summary_extension");
-            }
+            status(opts, &format!("Creating {}", repo.label()));
+            verify_summary(&client, repo, &sumurl, res.body())?;
+            let pkgs = parse_summary(repo, e, res.body(), &mut progress)?;
+            let total = pkgs.len();
             db.insert_remote_repository(
                 repo.url(),
+                repo.label(),
                 prefix,
                 last_modified,
                 e,
-                sumstr.entries(),
+                digest.as_deref(),
+                &pkgs,
+                &mut |i| progress.record(i, total),
             )?;
+            progress.finish(total);
         }
 
         /* We're done, skip remaining suffixes */
-        break;
+        return Ok(changes);
     }
-    Ok(())
+
+    Err("no usable pkg_summary found".into())
 }
 
+/*
+ * Exit status used when one or more repositories could not be updated.  The
+ * remaining repositories are still updated, but the caller should know that
+ * the database is not entirely current.
+ */
+const EXIT_PARTIAL: i32 = 2;
+
 pub fn run(
     cfg: &config::Config,
     db: &mut PMDB,
-) -> Result<(), Box<std::error::Error>> {
+    opts: &Options,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let sel = Selection::new(cfg, &opts.targets)?;
+    let mut failed = 0;
+    let mut changes: Vec<Changes> = vec![];
     for prefix in cfg.prefixes() {
-        update_local_repository(&prefix, db)?;
-        if let Some(repos) = prefix.repositories() {
-            for repo in repos {
-                update_remote_repository(prefix.path(), repo, db)?;
+        if sel.local(prefix) {
+            if let Err(e) = update_local_repository(opts, prefix, db) {
+                eprintln!(
+                    "ERROR: Unable to record packages installed under {}: {}",
+                    prefix.path(),
+                    e
+                );
+                failed += 1;
+            }
+        }
+        for repo in prefix.enabled_repositories() {
+            if !sel.remote(prefix, repo) {
+                continue;
+            }
+            match update_remote_repository(cfg, opts, prefix.path(), repo, db) {
+                Ok(Some(c)) => {
+                    if !opts.changes_json {
+                        c.print();
+                    }
+                    changes.push(c);
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    eprintln!(
+                        "ERROR: Unable to update {}: {}",
+                        repo.label(),
+                        e
+                    );
+                    failed += 1;
+                }
             }
         }
     }
 
+    /*
+     * Keep pkg-vulnerabilities current so that vulnerable packages can be
+     * marked, but not being able to fetch it doesn't affect the update.  It
+     * is only fetched from the default location by "pm audit", so that
+     * updating only contacts the configured servers.
+     */
+    if opts.targets.is_empty() && cfg.vulnerabilities_configured() {
+        if let Err(e) = audit::refresh(cfg, db) {
+            eprintln!("WARNING: Unable to update pkg-vulnerabilities: {}", e);
+        }
+    }
+
+    if opts.changes_json || opts.save_changes.is_some() {
+        let json = serde_json::to_string_pretty(&changes)?;
+        if opts.changes_json {
+            println!("{}", json);
+        }
+        if let Some(path) = &opts.save_changes {
+            fs::write(path, format!("{}\n", json))
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+        }
+    }
+
+    if failed > 0 {
+        eprintln!(
+            "WARNING: Update incomplete, {} repositor{} failed",
+            failed,
+            if failed == 1 { "y" } else { "ies" }
+        );
+        std::process::exit(EXIT_PARTIAL);
+    }
+
+    Ok(())
+}
+
+/*
+ * Exit statuses used by --check, which exits 0 if everything is up to date.
+ * Any error, including failing to check a single repository, uses
+ * EXIT_FAILED so that it is never mistaken for pending updates.
+ */
+const EXIT_PENDING: i32 = 1;
+pub const EXIT_FAILED: i32 = 2;
+
+/*
+ * Return whether the packages installed under a prefix have changed since
+ * they were last recorded.
+ */
+fn check_local_repository(
+    prefix: &config::Prefix,
+    db: Option<&PMDB>,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let (mtime, ntime) = pkgdb_mtime(prefix)?;
+    let current = match db {
+        Some(db) => db.get_local_repository(prefix.path())?,
+        None => None,
+    };
+    Ok(match current {
+        Some(r) => !r.up_to_date(mtime, ntime),
+        None => true,
+    })
+}
+
+/*
+ * Return whether a remote repository has a different pkg_summary to the one
+ * last recorded.  Only the headers are requested, falling back to a
+ * conditional GET for servers that do not support HEAD.
+ */
+fn check_remote_repository(
+    cfg: &config::Config,
+    repo: &config::Repository,
+    db: Option<&PMDB>,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let client = fetch::client(cfg, repo)?;
+    let current = match db {
+        Some(db) => db.get_remote_repository(repo.url())?,
+        None => None,
+    };
+
+    for e in get_summary_extensions(repo) {
+        let sumurl = format!("{}/{}.{}", repo.url(), "pkg_summary", e);
+
+        /*
+         * As for updates, a conditional GET can't be trusted to say a
+         * repository with a published digest hasn't changed.
+         */
+        let mut res = fetch::head(&client, repo, &sumurl)?;
+        if res.status() == reqwest::StatusCode::METHOD_NOT_ALLOWED {
+            res = match &current {
+                Some(r)
+                    if r.summary_suffix() == e && repo.digest().is_none() =>
+                {
+                    fetch::get_if_modified(&client, repo, &sumurl, r.mtime())?
+                }
+                _ => fetch::get(&client, repo, &sumurl)?,
+            };
+            if res.status() == reqwest::StatusCode::NOT_MODIFIED {
+                return Ok(false);
+            }
+        }
+
+        if res.status() == reqwest::StatusCode::UNAUTHORIZED {
+            return Err(format!("{} returned {}", sumurl, res.status()).into());
+        }
+        if !res.status().is_success() {
+            continue;
+        }
+        let last_modified = match res.last_modified() {
+            Some(lm) => lm,
+            None => continue,
+        };
+
+        let digest = published_digest(&client, repo, &sumurl)?;
+        return Ok(match &current {
+            Some(r) => !r.up_to_date(last_modified, e, digest.as_deref()),
+            None => true,
+        });
+    }
+
+    Err("no usable pkg_summary found".into())
+}
+
+/*
+ * Report which repositories would be changed by an update, without touching
+ * the database.  db is None if there is no usable database yet.
+ */
+pub fn check(
+    cfg: &config::Config,
+    db: Option<&PMDB>,
+    targets: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let sel = Selection::new(cfg, targets)?;
+    let mut pending = 0;
+    let mut failed = 0;
+    for prefix in cfg.prefixes() {
+        let local = if sel.local(prefix) {
+            check_local_repository(prefix, db).map(Some)
+        } else {
+            Ok(None)
+        };
+        match local {
+            Ok(Some(true)) => {
+                println!(
+                    "Packages installed under {} have changed",
+                    prefix.path()
+                );
+                pending += 1;
+            }
+            Ok(Some(false)) => {
+                println!(
+                    "Packages installed under {} are up to date",
+                    prefix.path()
+                );
+            }
+            Err(e) => {
+                eprintln!("ERROR: Unable to check {}: {}", prefix.path(), e);
+                failed += 1;
+            }
+            Ok(None) => {}
+        }
+        for repo in prefix.enabled_repositories() {
+            if !sel.remote(prefix, repo) {
+                continue;
+            }
+            match check_remote_repository(cfg, repo, db) {
+                Ok(true) => {
+                    println!("{} has updates pending", repo.label());
+                    pending += 1;
+                }
+                Ok(false) => println!("{} is up to date", repo.label()),
+                Err(e) => {
+                    eprintln!("ERROR: Unable to check {}: {}", repo.label(), e);
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    if failed > 0 {
+        eprintln!(
+            "WARNING: Check incomplete, {} repositor{} failed",
+            failed,
+            if failed == 1 { "y" } else { "ies" }
+        );
+        std::process::exit(EXIT_FAILED);
+    }
+    if pending > 0 {
+        std::process::exit(EXIT_PENDING);
+    }
+
     Ok(())
 }
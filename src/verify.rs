@@ -0,0 +1,582 @@
+/*
+ * Copyright (c) 2019 Jonathan Perkin <jonathan@perkin.org.uk>
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ *
+ * verify.rs - verify signed binary packages, handle "pm verify-pkg".
+ *
+ * A signed package as created by "pkg_admin gpg-sign-package" is an
+ * uncompressed tar archive containing +PKG_HASH, +PKG_GPG_SIGNATURE, and
+ * then the original package.  +PKG_HASH lists a digest for each block of
+ * the original package, and +PKG_GPG_SIGNATURE is a detached signature
+ * over +PKG_HASH, so checking both verifies the whole package.
+ */
+
+extern crate openssl;
+extern crate tar;
+
+use crate::config;
+use openssl::hash::{Hasher, MessageDigest};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/*
+ * The contents of +PKG_HASH.
+ */
+struct PkgHash {
+    pkgname: String,
+    algorithm: MessageDigest,
+    block_size: usize,
+    file_size: u64,
+    hashes: Vec<String>,
+}
+
+impl PkgHash {
+    fn parse(contents: &str) -> Result<PkgHash, String> {
+        let mut lines = contents.lines();
+        if lines.next() != Some("pkgsrc signature") {
+            return Err("+PKG_HASH: missing header".to_string());
+        }
+        let mut pkgname = None;
+        let mut algorithm = None;
+        let mut block_size = None;
+        let mut file_size = None;
+        let mut version = None;
+        for line in lines.by_ref().skip_while(|l| l.is_empty()) {
+            if line.is_empty() {
+                break;
+            }
+            let (key, value) = match line.find(": ") {
+                Some(i) => (&line[..i], &line[i + 2..]),
+                None => return Err(format!("+PKG_HASH: invalid \"{}\"", line)),
+            };
+            match key {
+                "version" => version = Some(value),
+                "pkgname" => pkgname = Some(value.to_string()),
+                "algorithm" => {
+                    algorithm = Some(match value {
+                        "SHA256" => MessageDigest::sha256(),
+                        "SHA512" => MessageDigest::sha512(),
+                        _ => {
+                            return Err(format!(
+                                "+PKG_HASH: unsupported algorithm {}",
+                                value
+                            ))
+                        }
+                    })
+                }
+                "block size" => block_size = value.parse().ok(),
+                "file size" => file_size = value.parse().ok(),
+                _ => {}
+            }
+        }
+        if version != Some("1") {
+            return Err("+PKG_HASH: unsupported version".to_string());
+        }
+        let mut hashes = vec![];
+        let mut ended = false;
+        for line in lines {
+            if line == "end pkgsrc signature" {
+                ended = true;
+                break;
+            }
+            hashes.push(line.to_lowercase());
+        }
+        if !ended {
+            return Err("+PKG_HASH: missing trailer".to_string());
+        }
+        match (pkgname, algorithm, block_size, file_size) {
+            (
+                Some(pkgname),
+                Some(algorithm),
+                Some(block_size),
+                Some(file_size),
+            ) if block_size > 0 => Ok(PkgHash {
+                pkgname,
+                algorithm,
+                block_size,
+                file_size,
+                hashes,
+            }),
+            _ => Err("+PKG_HASH: missing or invalid field".to_string()),
+        }
+    }
+
+    /*
+     * Check the original package against the block hashes.
+     */
+    fn check(&self, input: &mut dyn Read) -> Result<(), String> {
+        let mut buf = vec![0; self.block_size];
+        let mut size = 0;
+        let mut block = 0;
+        loop {
+            /* Fill a whole block, short reads are allowed */
+            let mut len = 0;
+            while len < buf.len() {
+                let n = input
+                    .read(&mut buf[len..])
+                    .map_err(|e| format!("reading package: {}", e))?;
+                if n == 0 {
+                    break;
+                }
+                len += n;
+            }
+            if len == 0 {
+                break;
+            }
+            let mut hasher =
+                Hasher::new(self.algorithm).map_err(|e| e.to_string())?;
+            hasher.update(&buf[..len]).map_err(|e| e.to_string())?;
+            let hex: String = hasher
+                .finish()
+                .map_err(|e| e.to_string())?
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect();
+            if self.hashes.get(block) != Some(&hex) {
+                return Err(format!(
+                    "block {} does not match +PKG_HASH",
+                    block
+                ));
+            }
+            size += len as u64;
+            block += 1;
+        }
+        if size != self.file_size || block != self.hashes.len() {
+            return Err(format!(
+                "package is {} bytes, +PKG_HASH expects {}",
+                size, self.file_size
+            ));
+        }
+        Ok(())
+    }
+}
+
+/*
+ * A temporary directory only accessible by the current user, with a random
+ * name so that it cannot be created in advance by anyone else.  It is removed
+ * along with its contents when dropped.
+ */
+struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    fn new() -> Result<TempDir, String> {
+        loop {
+            let mut rand = [0; 8];
+            openssl::rand::rand_bytes(&mut rand).map_err(|e| e.to_string())?;
+            let name: String =
+                rand.iter().map(|b| format!("{:02x}", b)).collect();
+            let path = std::env::temp_dir().join(format!("pm-verify.{}", name));
+            match fs::DirBuilder::new().mode(0o700).create(&path) {
+                Ok(()) => return Ok(TempDir { path }),
+                Err(ref e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    continue
+                }
+                Err(e) => return Err(format!("{}: {}", path.display(), e)),
+            }
+        }
+    }
+
+    /*
+     * Create a new file in the directory, failing rather than following
+     * anything that is already there.
+     */
+    fn write(&self, name: &str, contents: &[u8]) -> Result<PathBuf, String> {
+        let path = self.path.join(name);
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+            .and_then(|mut file| file.write_all(contents))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/*
+ * Why a GPG signature was not accepted.  Unchecked means that gpg could not
+ * tell either way, for example because the key is not in the keyring.
+ */
+#[derive(Debug)]
+pub enum GpgError {
+    Bad(String),
+    Unchecked(String),
+}
+
+impl fmt::Display for GpgError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GpgError::Bad(e) => write!(f, "bad signature: {}", e),
+            GpgError::Unchecked(e) => {
+                write!(f, "unable to check signature: {}", e)
+            }
+        }
+    }
+}
+
+/*
+ * Run gpg to verify a signature, returning who it was signed by and, for a
+ * clearsigned message, the text that was signed as gpg saw it.  The
+ * signature is written to a private temporary file and any data for a
+ * detached signature passed on stdin, in the same way as pkg_install.
+ */
+fn run_gpg(
+    gpg: &str,
+    keyring: Option<&str>,
+    signature: &[u8],
+    data: Option<&[u8]>,
+) -> Result<(String, Option<Vec<u8>>), GpgError> {
+    let tmpdir = TempDir::new().map_err(GpgError::Unchecked)?;
+    let sigpath = tmpdir
+        .write("signature", signature)
+        .map_err(GpgError::Unchecked)?;
+    let textpath = tmpdir.path.join("text");
+
+    let mut cmd = Command::new(gpg);
+    if let Some(keyring) = keyring {
+        cmd.args(["--no-default-keyring", "--keyring", keyring]);
+    }
+    cmd.args(["--batch", "--status-fd", "1"]);
+    if data.is_some() {
+        cmd.arg("--verify")
+            .arg(&sigpath)
+            .arg("-")
+            .stdin(Stdio::piped());
+    } else {
+        cmd.arg("--output")
+            .arg(&textpath)
+            .arg("--verify")
+            .arg(&sigpath)
+            .stdin(Stdio::null());
+    }
+    let child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn();
+    let output = child.and_then(|mut child| {
+        /* gpg may exit early, the result is all that matters */
+        if let (Some(mut stdin), Some(data)) = (child.stdin.take(), data) {
+            let _ = stdin.write_all(data);
+        }
+        child.wait_with_output()
+    });
+    let output =
+        output.map_err(|e| GpgError::Unchecked(format!("{}: {}", gpg, e)))?;
+
+    let status = String::from_utf8_lossy(&output.stdout);
+    let signer = status
+        .lines()
+        .find_map(|l| l.strip_prefix("[GNUPG:] GOODSIG "))
+        .map(|s| s.split_once(' ').map_or(s, |(_, name)| name).to_string());
+    match signer {
+        Some(signer) if output.status.success() => {
+            let text = match data {
+                Some(_) => None,
+                None => Some(fs::read(&textpath).map_err(|e| {
+                    GpgError::Unchecked(format!("{}: {}", gpg, e))
+                })?),
+            };
+            Ok((signer, text))
+        }
+        _ => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let e = stderr.lines().last().unwrap_or("gpg failed").to_string();
+            if status.lines().any(|l| l.starts_with("[GNUPG:] BADSIG ")) {
+                Err(GpgError::Bad(e))
+            } else {
+                Err(GpgError::Unchecked(e))
+            }
+        }
+    }
+}
+
+/*
+ * Verify a detached GPG signature over data, returning who it was signed by.
+ */
+pub fn verify_gpg(
+    gpg: &str,
+    keyring: Option<&str>,
+    signature: &[u8],
+    data: &[u8],
+) -> Result<String, GpgError> {
+    run_gpg(gpg, keyring, signature, Some(data)).map(|(signer, _)| signer)
+}
+
+/*
+ * Verify a clearsigned message, returning the text that was signed.  Only
+ * this text should be used, as anything else in the message is not covered
+ * by the signature.
+ */
+pub fn verify_clearsigned(
+    gpg: &str,
+    keyring: Option<&str>,
+    message: &[u8],
+) -> Result<Vec<u8>, GpgError> {
+    run_gpg(gpg, keyring, message, None)
+        .map(|(_, text)| text.unwrap_or_default())
+}
+
+/*
+ * Result of checking a package.
+ */
+enum Verified {
+    Signed { pkgname: String, signer: String },
+    Unsigned,
+}
+
+fn verify(prefix: &config::Prefix, path: &Path) -> Result<Verified, String> {
+    let mut file =
+        File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    /*
+     * Unsigned packages are compressed tar archives, whereas signed
+     * packages are always an uncompressed tar archive.
+     */
+    let mut magic = [0; 6];
+    let n = file.read(&mut magic).map_err(|e| e.to_string())?;
+    let magic = &magic[..n];
+    if magic.starts_with(&[0x1f, 0x8b])
+        || magic.starts_with(b"BZh")
+        || magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0])
+    {
+        return Ok(Verified::Unsigned);
+    }
+    let file =
+        File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    let mut archive = tar::Archive::new(file);
+    let mut entries = archive.entries().map_err(|e| e.to_string())?;
+    let mut next = || -> Result<Option<(String, tar::Entry<File>)>, String> {
+        match entries.next() {
+            Some(Ok(entry)) => {
+                let name = entry
+                    .path()
+                    .map_err(|e| e.to_string())?
+                    .to_string_lossy()
+                    .to_string();
+                Ok(Some((name, entry)))
+            }
+            Some(Err(e)) => Err(format!("invalid package: {}", e)),
+            None => Ok(None),
+        }
+    };
+
+    let hash = match next()? {
+        Some((name, mut entry)) if name == "+PKG_HASH" => {
+            let mut contents = String::new();
+            entry
+                .read_to_string(&mut contents)
+                .map_err(|e| format!("+PKG_HASH: {}", e))?;
+            contents
+        }
+        _ => return Ok(Verified::Unsigned),
+    };
+    let pkghash = PkgHash::parse(&hash)?;
+
+    let signature = match next()? {
+        Some((name, mut entry)) if name == "+PKG_GPG_SIGNATURE" => {
+            let mut sig = vec![];
+            entry
+                .read_to_end(&mut sig)
+                .map_err(|e| format!("+PKG_GPG_SIGNATURE: {}", e))?;
+            sig
+        }
+        Some((name, _)) if name == "+PKG_SIGNATURE" => {
+            return Err("X.509 signatures are not supported".to_string());
+        }
+        _ => return Err("missing +PKG_GPG_SIGNATURE".to_string()),
+    };
+
+    match next()? {
+        Some((_, mut entry)) => pkghash.check(&mut entry)?,
+        None => return Err("missing package contents".to_string()),
+    }
+    let signer = verify_gpg(
+        &prefix.gpg(),
+        prefix.gpg_keyring().as_deref(),
+        &signature,
+        hash.as_bytes(),
+    )
+    .map_err(|e| e.to_string())?;
+
+    Ok(Verified::Signed {
+        pkgname: pkghash.pkgname,
+        signer,
+    })
+}
+
+/*
+ * Verify a binary package file.  Unsigned packages are only an error if the
+ * prefix requires signatures.
+ */
+pub fn run(
+    cfg: &config::Config,
+    path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let prefix = match cfg.get_prefix(cfg.prefix()) {
+        Some(prefix) => prefix,
+        None => {
+            eprintln!("ERROR: Prefix {} is not configured", cfg.prefix());
+            std::process::exit(1);
+        }
+    };
+    match verify(prefix, path) {
+        Ok(Verified::Signed { pkgname, signer }) => {
+            println!(
+                "{}: {} has a good signature from {}",
+                path.display(),
+                pkgname,
+                signer
+            );
+        }
+        Ok(Verified::Unsigned) if prefix.verify_signatures() => {
+            eprintln!(
+                "ERROR: {}: package is not signed, and {} requires signed \
+                 packages",
+                path.display(),
+                prefix.path()
+            );
+            std::process::exit(1);
+        }
+        Ok(Verified::Unsigned) => {
+            println!("{}: package is not signed", path.display());
+        }
+        Err(e) => {
+            eprintln!("ERROR: {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: &[u8] = b"0123456789";
+
+    fn sha256_hex(data: &[u8]) -> String {
+        openssl::sha::sha256(data)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    /*
+     * A +PKG_HASH for DATA with four byte blocks.
+     */
+    fn pkg_hash() -> String {
+        format!(
+            "pkgsrc signature\n\
+             \n\
+             version: 1\n\
+             pkgname: test-1.0\n\
+             type: inline\n\
+             algorithm: SHA256\n\
+             block size: 4\n\
+             file size: 10\n\
+             \n\
+             {}\n{}\n{}\n\
+             end pkgsrc signature\n",
+            sha256_hex(b"0123"),
+            sha256_hex(b"4567"),
+            sha256_hex(b"89")
+        )
+    }
+
+    fn check(data: &[u8]) -> Result<(), String> {
+        PkgHash::parse(&pkg_hash())?.check(&mut &data[..])
+    }
+
+    /*
+     * A reader that only returns a single byte at a time.
+     */
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.0.is_empty() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.0[0];
+            self.0 = &self.0[1..];
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn parse() {
+        let pkghash = PkgHash::parse(&pkg_hash()).unwrap();
+        assert_eq!(pkghash.pkgname, "test-1.0");
+        assert_eq!(pkghash.block_size, 4);
+        assert_eq!(pkghash.file_size, 10);
+        assert_eq!(pkghash.hashes.len(), 3);
+    }
+
+    #[test]
+    fn matching() {
+        assert_eq!(check(DATA), Ok(()));
+        let pkghash = PkgHash::parse(&pkg_hash()).unwrap();
+        assert_eq!(pkghash.check(&mut Trickle(DATA)), Ok(()));
+    }
+
+    #[test]
+    fn mismatch() {
+        assert_eq!(
+            check(b"0123X56789"),
+            Err("block 1 does not match +PKG_HASH".to_string())
+        );
+        assert_eq!(
+            check(b"0123456789ab"),
+            Err("block 2 does not match +PKG_HASH".to_string())
+        );
+    }
+
+    #[test]
+    fn truncated() {
+        assert_eq!(
+            check(b"01234567"),
+            Err("package is 8 bytes, +PKG_HASH expects 10".to_string())
+        );
+        assert_eq!(
+            check(b""),
+            Err("package is 0 bytes, +PKG_HASH expects 10".to_string())
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        let good = pkg_hash();
+        for bad in &[
+            good.replace("pkgsrc signature\n\n", "\n"),
+            good.replace("version: 1", "version: 2"),
+            good.replace("SHA256", "MD5"),
+            good.replace("block size: 4", "block size: 0"),
+            good.replace("file size: 10", "file size: ten"),
+            good.replace("end pkgsrc signature\n", ""),
+            good.replace("type: inline", "type inline"),
+        ] {
+            assert!(PkgHash::parse(bad).is_err(), "{}", bad);
+        }
+    }
+}